@@ -0,0 +1,274 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use rust_stemmers::Algorithm;
+
+use super::{
+    ApostrophePolicy, Bm25, CercamiError, DefaultOperator, DefaultTokenizer, FieldBoosts, Index,
+    Monitor, Normalizer, Tokenizer,
+};
+
+#[derive(Default)]
+pub struct IndexBuilder {
+    language: Option<Algorithm>,
+    stop_words: Option<Vec<String>>,
+    stemming: Option<bool>,
+    fold_diacritics: Option<bool>,
+    min_token_len: Option<usize>,
+    max_token_len: Option<usize>,
+    case_sensitive: Option<bool>,
+    apostrophes: Option<ApostrophePolicy>,
+    normalizer: Option<Normalizer>,
+    tokenizer: Option<Box<dyn Tokenizer>>,
+    ngram_size: Option<usize>,
+    bm25: Option<Bm25>,
+    field_boosts: Option<FieldBoosts>,
+    default_operator: DefaultOperator,
+    dedup: bool,
+    strict: Option<bool>,
+    skip_missing: bool,
+    query_cache: usize,
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl IndexBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub const fn language(mut self, language: Algorithm) -> Self {
+        self.language = Some(language);
+        self
+    }
+
+    #[must_use]
+    pub fn stop_words(mut self, stop_words: Vec<String>) -> Self {
+        self.stop_words = Some(stop_words);
+        self
+    }
+
+    #[must_use]
+    pub const fn stemming(mut self, stemming: bool) -> Self {
+        self.stemming = Some(stemming);
+        self
+    }
+
+    #[must_use]
+    pub const fn fold_diacritics(mut self, fold_diacritics: bool) -> Self {
+        self.fold_diacritics = Some(fold_diacritics);
+        self
+    }
+
+    #[must_use]
+    pub const fn min_token_len(mut self, min_token_len: usize) -> Self {
+        self.min_token_len = Some(min_token_len);
+        self
+    }
+
+    #[must_use]
+    pub const fn max_token_len(mut self, max_token_len: usize) -> Self {
+        self.max_token_len = Some(max_token_len);
+        self
+    }
+
+    #[must_use]
+    pub const fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = Some(case_sensitive);
+        self
+    }
+
+    #[must_use]
+    pub const fn apostrophes(mut self, apostrophes: ApostrophePolicy) -> Self {
+        self.apostrophes = Some(apostrophes);
+        self
+    }
+
+    /// Normalizes words with `normalizer`, e.g. a lemmatizer, instead of
+    /// stemming them.
+    #[must_use]
+    pub fn normalizer<F>(mut self, normalizer: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.normalizer = Some(Box::new(normalizer));
+        self
+    }
+
+    /// Replaces the default tokenizer; conflicts with the tokenizer settings above.
+    #[must_use]
+    pub fn tokenizer<T: Tokenizer + 'static>(mut self, tokenizer: T) -> Self {
+        self.tokenizer = Some(Box::new(tokenizer));
+        self
+    }
+
+    #[must_use]
+    pub const fn ngrams(mut self, size: usize) -> Self {
+        self.ngram_size = Some(size);
+        self
+    }
+
+    #[must_use]
+    pub const fn bm25(mut self, bm25: Bm25) -> Self {
+        self.bm25 = Some(bm25);
+        self
+    }
+
+    #[must_use]
+    pub const fn field_boosts(mut self, field_boosts: FieldBoosts) -> Self {
+        self.field_boosts = Some(field_boosts);
+        self
+    }
+
+    #[must_use]
+    pub const fn default_operator(mut self, operator: DefaultOperator) -> Self {
+        self.default_operator = operator;
+        self
+    }
+
+    /// Skips documents whose text is identical to an already indexed one,
+    /// keeping the first id.
+    #[must_use]
+    pub const fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// With `strict(false)`, XML documents that cannot be used, e.g. because
+    /// a field is missing, are skipped and counted in
+    /// `IndexStats::malformed_skipped` instead of failing the load. Errors in
+    /// the XML structure itself still fail it.
+    #[must_use]
+    pub const fn strict(mut self, strict: bool) -> Self {
+        self.strict = Some(strict);
+        self
+    }
+
+    /// Lets AND queries skip terms that match no document instead of
+    /// returning nothing. A query whose terms all miss still matches nothing.
+    #[must_use]
+    pub const fn skip_missing(mut self, skip_missing: bool) -> Self {
+        self.skip_missing = skip_missing;
+        self
+    }
+
+    /// Caches the results of up to `capacity` distinct queries; see
+    /// `Index::set_query_cache`.
+    #[must_use]
+    pub const fn query_cache(mut self, capacity: usize) -> Self {
+        self.query_cache = capacity;
+        self
+    }
+
+    /// Makes `build_from_xml` stop with `CercamiError::Cancelled` once `cancel`
+    /// is set. The partially built index is dropped.
+    #[must_use]
+    pub fn cancel_flag(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn build(self) -> Result<Index, CercamiError> {
+        if self.ngram_size == Some(0) {
+            return Err(invalid("n-gram size must be at least 1"));
+        }
+
+        if let Some(bm25) = self.bm25 {
+            if bm25.k1 < 0.0 || !(0.0..=1.0).contains(&bm25.b) {
+                return Err(invalid("BM25 requires k1 >= 0 and 0 <= b <= 1"));
+            }
+        }
+
+        if let Some(boosts) = self.field_boosts {
+            if boosts.title < 0.0 || boosts.text < 0.0 {
+                return Err(invalid("field boosts must not be negative"));
+            }
+        }
+
+        let ngram_size = self.ngram_size;
+        let default_operator = self.default_operator;
+        let dedup = self.dedup;
+        let strict = self.strict.unwrap_or(true);
+        let skip_missing = self.skip_missing;
+        let query_cache = self.query_cache;
+        let bm25 = self.bm25.unwrap_or_default();
+        let field_boosts = self.field_boosts.unwrap_or_default();
+        let tokenizer = self.into_tokenizer()?;
+
+        let mut index = Index {
+            dedup,
+            strict,
+            tokenizer,
+            ngram_size,
+            bm25,
+            field_boosts,
+            default_operator,
+            skip_missing,
+            ..Index::default()
+        };
+        index.set_query_cache(query_cache);
+        Ok(index)
+    }
+
+    pub fn build_from_xml(mut self, db_path: &str) -> Result<Index, CercamiError> {
+        let cancel = self.cancel.take();
+        let mut index = self.build()?;
+        index.index_file_monitored(
+            db_path,
+            &mut Monitor {
+                cancel: cancel.as_deref(),
+                ..Monitor::default()
+            },
+        )?;
+        Ok(index)
+    }
+
+    fn into_tokenizer(self) -> Result<Box<dyn Tokenizer>, CercamiError> {
+        let customized = self.language.is_some()
+            || self.stop_words.is_some()
+            || self.stemming.is_some()
+            || self.fold_diacritics.is_some()
+            || self.min_token_len.is_some()
+            || self.max_token_len.is_some()
+            || self.case_sensitive.is_some()
+            || self.apostrophes.is_some()
+            || self.normalizer.is_some();
+
+        if let Some(tokenizer) = self.tokenizer {
+            if customized {
+                return Err(invalid(
+                    "tokenizer settings cannot be combined with a custom tokenizer",
+                ));
+            }
+            return Ok(tokenizer);
+        }
+
+        let min_token_len = self.min_token_len.unwrap_or(1);
+        let max_token_len = self.max_token_len.unwrap_or(usize::MAX);
+        if min_token_len > max_token_len {
+            return Err(invalid(&format!(
+                "min_token_len ({min_token_len}) is greater than max_token_len ({max_token_len})"
+            )));
+        }
+
+        let mut tokenizer = DefaultTokenizer::new(self.language.unwrap_or(Algorithm::English))
+            .stemming(self.stemming.unwrap_or(true))
+            .fold_diacritics(self.fold_diacritics.unwrap_or(false))
+            .token_length(min_token_len, max_token_len)
+            .case_sensitive(self.case_sensitive.unwrap_or(false))
+            .apostrophes(self.apostrophes.unwrap_or_default());
+        if let Some(stop_words) = self.stop_words {
+            tokenizer = tokenizer.stop_words(stop_words);
+        }
+        if let Some(normalizer) = self.normalizer {
+            tokenizer = tokenizer.normalizer(normalizer);
+        }
+
+        Ok(Box::new(tokenizer))
+    }
+}
+
+fn invalid(message: &str) -> CercamiError {
+    CercamiError::Config(message.to_string())
+}
@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+
+use croaring::bitmap::Bitmap;
+
+/// Least recently used query results, keyed by the whitespace-normalized
+/// query. Eviction scans every entry, which is fine for small capacities.
+pub struct QueryCache {
+    capacity: usize,
+    entries: HashMap<String, (Bitmap, u64)>,
+    clock: u64,
+}
+
+impl QueryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn key(query: &str) -> String {
+        query.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<Bitmap> {
+        self.clock += 1;
+        let (results, used) = self.entries.get_mut(key)?;
+        *used = self.clock;
+        Some(results.clone())
+    }
+
+    pub fn insert(&mut self, key: String, results: Bitmap) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.clock += 1;
+        self.entries.insert(key, (results, self.clock));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,102 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum CercamiError {
+    Io(io::Error),
+    Xml(quick_xml::Error),
+    Json(serde_json::Error),
+    Csv(csv::Error),
+    Regex(regex::Error),
+    Bincode(bincode::Error),
+    #[cfg(feature = "watch")]
+    Watch(notify::Error),
+    InvalidInput(String),
+    UnsupportedInput(String),
+    Config(String),
+    Cancelled,
+}
+
+impl fmt::Display for CercamiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Xml(err) => write!(f, "invalid XML: {err}"),
+            Self::Json(err) => write!(f, "invalid JSON: {err}"),
+            Self::Csv(err) => write!(f, "invalid CSV: {err}"),
+            Self::Regex(err) => write!(f, "invalid pattern: {err}"),
+            Self::Bincode(err) => write!(f, "invalid index file: {err}"),
+            #[cfg(feature = "watch")]
+            Self::Watch(err) => write!(f, "cannot watch file: {err}"),
+            Self::Cancelled => write!(f, "indexing was cancelled"),
+            Self::InvalidInput(message)
+            | Self::UnsupportedInput(message)
+            | Self::Config(message) => {
+                write!(f, "{message}")
+            }
+        }
+    }
+}
+
+impl error::Error for CercamiError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Xml(err) => Some(err),
+            Self::Json(err) => Some(err),
+            Self::Csv(err) => Some(err),
+            Self::Regex(err) => Some(err),
+            Self::Bincode(err) => Some(err),
+            #[cfg(feature = "watch")]
+            Self::Watch(err) => Some(err),
+            Self::InvalidInput(_)
+            | Self::UnsupportedInput(_)
+            | Self::Config(_)
+            | Self::Cancelled => None,
+        }
+    }
+}
+
+impl From<io::Error> for CercamiError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<quick_xml::Error> for CercamiError {
+    fn from(err: quick_xml::Error) -> Self {
+        Self::Xml(err)
+    }
+}
+
+impl From<serde_json::Error> for CercamiError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+impl From<csv::Error> for CercamiError {
+    fn from(err: csv::Error) -> Self {
+        Self::Csv(err)
+    }
+}
+
+impl From<regex::Error> for CercamiError {
+    fn from(err: regex::Error) -> Self {
+        Self::Regex(err)
+    }
+}
+
+impl From<bincode::Error> for CercamiError {
+    fn from(err: bincode::Error) -> Self {
+        Self::Bincode(err)
+    }
+}
+
+#[cfg(feature = "watch")]
+impl From<notify::Error> for CercamiError {
+    fn from(err: notify::Error) -> Self {
+        Self::Watch(err)
+    }
+}
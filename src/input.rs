@@ -0,0 +1,834 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use flate2::read::GzDecoder;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use super::{CercamiError, Document, Index};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const BATCH_SIZE: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct CsvColumns {
+    pub title: String,
+    pub url: String,
+    pub text: String,
+}
+
+impl Default for CsvColumns {
+    fn default() -> Self {
+        Self {
+            title: "title".to_string(),
+            url: "url".to_string(),
+            text: "text".to_string(),
+        }
+    }
+}
+
+/// Names of the XML elements holding each document and its fields. The
+/// optional `id` element is only read by `Index::apply_delta`.
+#[derive(Debug, Clone)]
+pub struct XmlElements {
+    pub document: String,
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    pub text: String,
+}
+
+impl Default for XmlElements {
+    fn default() -> Self {
+        Self {
+            document: "doc".to_string(),
+            id: "id".to_string(),
+            title: "title".to_string(),
+            url: "url".to_string(),
+            text: "abstract".to_string(),
+        }
+    }
+}
+
+/// Observes a dump while it is read: `progress` gets the number of documents
+/// read after every `every` of them, and setting `cancel` stops the read.
+#[derive(Default)]
+pub struct Monitor<'a> {
+    pub every: usize,
+    pub progress: Option<&'a mut dyn FnMut(usize)>,
+    pub cancel: Option<&'a AtomicBool>,
+}
+
+impl Index {
+    pub fn from_json(path: &str) -> Result<Self, CercamiError> {
+        let docs: Vec<Document> = serde_json::from_reader(open(path)?)
+            .map_err(|err| CercamiError::InvalidInput(format!("invalid JSON in {path}: {err}")))?;
+
+        let mut index = Self::default();
+        index.add_numbered(docs)?;
+        Ok(index)
+    }
+
+    /// Like `from_json`, parsing `json` directly.
+    pub fn from_json_str(json: &str) -> Result<Self, CercamiError> {
+        let docs: Vec<Document> = serde_json::from_str(json)
+            .map_err(|err| CercamiError::InvalidInput(format!("invalid JSON: {err}")))?;
+
+        let mut index = Self::default();
+        index.add_numbered(docs)?;
+        Ok(index)
+    }
+
+    /// Blank lines are skipped, and counted in
+    /// `IndexStats::blank_lines_skipped`.
+    pub fn from_ndjson(path: &str) -> Result<Self, CercamiError> {
+        let mut index = Self::default();
+        let mut batch = Batch::new(&mut index);
+        let mut id = 0;
+        let mut skipped = Vec::new();
+
+        for (number, line) in (1..).zip(open(path)?.lines()) {
+            let line = line?;
+            if line.trim().is_empty() {
+                skipped.push(format!("blank line {number} in {path}"));
+                continue;
+            }
+
+            let mut doc: Document = serde_json::from_str(&line).map_err(|err| {
+                CercamiError::InvalidInput(format!(
+                    "invalid JSON on line {number} of {path}: {err}"
+                ))
+            })?;
+            doc.id = id;
+            batch.push(doc);
+            id += 1;
+        }
+
+        batch.finish();
+        index.blank_lines_skipped += skipped.len() as u64;
+        index.skipped.extend(skipped);
+        Ok(index)
+    }
+
+    pub fn from_csv(path: &str) -> Result<Self, CercamiError> {
+        Self::from_csv_with_columns(path, &CsvColumns::default())
+    }
+
+    pub fn from_csv_with_columns(path: &str, columns: &CsvColumns) -> Result<Self, CercamiError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(open(path)?);
+        let headers = reader.headers()?.clone();
+
+        let position = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| CercamiError::InvalidInput(format!("{path} has no `{name}` column")))
+        };
+        let title = position(&columns.title)?;
+        let url = position(&columns.url)?;
+        let text = position(&columns.text)?;
+
+        let mut index = Self::default();
+        let mut batch = Batch::new(&mut index);
+
+        for (id, record) in (0..).zip(reader.records()) {
+            let record = record?;
+            let number = id + 1;
+            let field = |column: usize, name: &str| {
+                record.get(column).map(str::to_string).ok_or_else(|| {
+                    CercamiError::InvalidInput(format!(
+                        "record {number} of {path} is missing `{name}`"
+                    ))
+                })
+            };
+
+            let doc = Document {
+                title: field(title, &columns.title)?,
+                url: field(url, &columns.url)?,
+                text: field(text, &columns.text)?,
+                id,
+            };
+            batch.push(doc);
+        }
+
+        batch.finish();
+        Ok(index)
+    }
+
+    pub fn from_dir(path: &str) -> Result<Self, CercamiError> {
+        Self::from_dir_with_extension(path, "txt")
+    }
+
+    /// Files and subdirectories that cannot be read are skipped, and counted
+    /// in `IndexStats::files_skipped`. Symlinked directories are not followed.
+    pub fn from_dir_with_extension(path: &str, extension: &str) -> Result<Self, CercamiError> {
+        let mut files = Vec::new();
+        let mut skipped = Vec::new();
+        collect_files(Path::new(path), extension, &mut files, &mut skipped)?;
+        files.sort();
+
+        let mut index = Self::default();
+        let mut batch = Batch::new(&mut index);
+        let mut id = 0;
+
+        for file in files {
+            let text = match fs::read_to_string(&file) {
+                Ok(text) => text,
+                Err(err) => {
+                    skipped.push(format!("{}: {err}", file.display()));
+                    continue;
+                }
+            };
+
+            let title = file
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+            let doc = Document {
+                title,
+                url: file.display().to_string(),
+                text,
+                id,
+            };
+            batch.push(doc);
+            id += 1;
+        }
+
+        batch.finish();
+        index.files_skipped += skipped.len() as u64;
+        index.skipped.extend(skipped);
+        Ok(index)
+    }
+
+    /// Indexes the XML dump read from `reader`, which may be gzip-compressed.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, CercamiError> {
+        Self::from_reader_with_elements(reader, &XmlElements::default())
+    }
+
+    /// Like `new`, parsing the XML dump `xml` directly.
+    pub fn from_xml_str(xml: &str) -> Result<Self, CercamiError> {
+        Self::from_reader(xml.as_bytes())
+    }
+
+    pub fn from_reader_with_elements<R: Read>(
+        reader: R,
+        elements: &XmlElements,
+    ) -> Result<Self, CercamiError> {
+        let mut index = Self::default();
+        index.index_dump(reader, "input", elements, &mut Monitor::default())?;
+        Ok(index)
+    }
+
+    /// Like `new`, for dumps whose elements are named differently.
+    pub fn from_xml_with_elements(
+        db_path: &str,
+        elements: &XmlElements,
+    ) -> Result<Self, CercamiError> {
+        let mut index = Self::default();
+        index.index_dump(
+            open_dump(db_path)?,
+            db_path,
+            elements,
+            &mut Monitor::default(),
+        )?;
+        Ok(index)
+    }
+
+    pub(crate) fn index_file(&mut self, db_path: &str) -> Result<(), CercamiError> {
+        self.index_file_monitored(db_path, &mut Monitor::default())
+    }
+
+    /// Indexes every dump in `db_paths`, optimizing the bitmaps once at the
+    /// end so the size measured before optimizing covers all of them.
+    pub(crate) fn index_files(&mut self, db_paths: &[&str]) -> Result<(), CercamiError> {
+        for db_path in db_paths {
+            self.read_dump(
+                open_dump(db_path)?,
+                db_path,
+                &XmlElements::default(),
+                &mut Monitor::default(),
+            )?;
+        }
+        self.optimize();
+        Ok(())
+    }
+
+    pub(crate) fn index_file_monitored(
+        &mut self,
+        db_path: &str,
+        monitor: &mut Monitor<'_>,
+    ) -> Result<(), CercamiError> {
+        self.index_dump(
+            open_dump(db_path)?,
+            db_path,
+            &XmlElements::default(),
+            monitor,
+        )
+    }
+
+    /// Indexes a whole XML dump, with the same checks whether it comes from a
+    /// file or not; `name` identifies it in errors.
+    fn index_dump<R: Read>(
+        &mut self,
+        reader: R,
+        name: &str,
+        elements: &XmlElements,
+        monitor: &mut Monitor<'_>,
+    ) -> Result<(), CercamiError> {
+        self.read_dump(reader, name, elements, monitor)?;
+        self.optimize();
+        Ok(())
+    }
+
+    /// Like `index_dump`, leaving the bitmaps unoptimized.
+    fn read_dump<R: Read>(
+        &mut self,
+        reader: R,
+        name: &str,
+        elements: &XmlElements,
+        monitor: &mut Monitor<'_>,
+    ) -> Result<(), CercamiError> {
+        let reader = decode_dump(reader, name)?;
+        let documents = self
+            .index_reader(reader, elements, monitor)
+            .map_err(|err| xml_error(name, err))?;
+        if documents == 0 {
+            return Err(CercamiError::InvalidInput(format!(
+                "{name} has no `<{}>` elements",
+                elements.document
+            )));
+        }
+        Ok(())
+    }
+
+    /// Applies an XML dump of new and changed documents. A document with an
+    /// `<id>` element replaces the indexed document with that id, or is added
+    /// under it if there is none; one without an id is added after the
+    /// largest id in the index and the delta. Nothing is applied if the
+    /// delta cannot be read.
+    pub fn apply_delta(&mut self, path: &str) -> Result<(), CercamiError> {
+        let reader = decode_dump(open_dump(path)?, path)?;
+        let strict = self.strict;
+        let mut malformed = 0;
+        let mut docs = Vec::new();
+
+        read_xml(reader, &XmlElements::default(), |doc| {
+            match doc {
+                Ok(doc) => docs.push(doc),
+                Err(err) if strict => return Err(err),
+                Err(_) => malformed += 1,
+            }
+            Ok(())
+        })
+        .map_err(|err| xml_error(path, err))?;
+
+        let largest = docs
+            .iter()
+            .filter_map(|(_, id)| *id)
+            .chain(self.documents.keys().copied())
+            .max();
+        let mut next = match largest {
+            Some(max) => max
+                .checked_add(1)
+                .ok_or_else(|| CercamiError::InvalidInput("too many documents".to_string()))?,
+            None => 0,
+        };
+
+        for (mut doc, id) in docs {
+            if let Some(id) = id {
+                doc.id = id;
+            } else {
+                doc.id = next;
+                next += 1;
+            }
+            self.update(&doc);
+        }
+
+        self.optimize();
+        self.malformed_skipped += malformed;
+        Ok(())
+    }
+
+    /// Documents are numbered after the largest id already indexed, so
+    /// several dumps can be read into one index. Returns the number of
+    /// document elements read, including skipped malformed ones.
+    fn index_reader<R: BufRead>(
+        &mut self,
+        reader: R,
+        elements: &XmlElements,
+        monitor: &mut Monitor<'_>,
+    ) -> Result<usize, CercamiError> {
+        let mut id = match self.documents.keys().max() {
+            Some(max) => max
+                .checked_add(1)
+                .ok_or_else(|| CercamiError::InvalidInput("too many documents".to_string()))?,
+            None => 0,
+        };
+        let strict = self.strict;
+        let mut malformed = 0;
+        let mut batch = Batch::new(self);
+        let mut read = 0;
+
+        let documents = read_xml(reader, elements, |doc| {
+            if monitor
+                .cancel
+                .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+            {
+                return Err(CercamiError::Cancelled);
+            }
+
+            let mut doc = match doc {
+                Ok((doc, _)) => doc,
+                Err(err) if strict => return Err(err),
+                Err(_) => {
+                    malformed += 1;
+                    return Ok(());
+                }
+            };
+            doc.id = id;
+            batch.push(doc);
+            id += 1;
+            read += 1;
+            if let Some(progress) = monitor.progress.as_mut() {
+                if read % monitor.every == 0 {
+                    progress(read);
+                }
+            }
+            Ok(())
+        })?;
+
+        batch.flush();
+        self.malformed_skipped += malformed;
+        Ok(documents)
+    }
+
+    fn add_numbered(&mut self, mut docs: Vec<Document>) -> Result<(), CercamiError> {
+        for (idx, doc) in docs.iter_mut().enumerate() {
+            doc.id = u32::try_from(idx)
+                .map_err(|_| CercamiError::InvalidInput("too many documents".to_string()))?;
+        }
+
+        self.add_all(&docs);
+        self.optimize();
+        Ok(())
+    }
+}
+
+fn open_dump(path: &str) -> Result<fs::File, CercamiError> {
+    fs::File::open(path)
+        .map_err(|err| io::Error::new(err.kind(), format!("cannot open {path}: {err}")).into())
+}
+
+fn decode_dump<'a, R: Read + 'a>(
+    reader: R,
+    name: &str,
+) -> Result<Box<dyn BufRead + 'a>, CercamiError> {
+    let mut reader = decode(reader)?;
+    if reader.fill_buf()?.is_empty() {
+        return Err(CercamiError::InvalidInput(format!("{name} is empty")));
+    }
+    Ok(reader)
+}
+
+fn xml_error(path: &str, err: CercamiError) -> CercamiError {
+    match err {
+        CercamiError::Xml(err) => {
+            CercamiError::InvalidInput(format!("{path} is not valid XML: {err}"))
+        }
+        err => err,
+    }
+}
+
+fn open(path: &str) -> io::Result<Box<dyn BufRead>> {
+    decode(fs::File::open(path)?)
+}
+
+fn decode<'a, R: Read + 'a>(reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    let mut reader = io::BufReader::new(reader);
+
+    if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(io::BufReader::new(GzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+struct Batch<'a> {
+    index: &'a mut Index,
+    docs: Vec<Document>,
+}
+
+impl<'a> Batch<'a> {
+    fn new(index: &'a mut Index) -> Self {
+        Batch {
+            index,
+            docs: Vec::with_capacity(BATCH_SIZE),
+        }
+    }
+
+    fn push(&mut self, doc: Document) {
+        self.docs.push(doc);
+        if self.docs.len() == BATCH_SIZE {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.index.add_all(&self.docs);
+        self.docs.clear();
+    }
+
+    fn finish(mut self) {
+        self.flush();
+        self.index.optimize();
+    }
+}
+
+#[derive(Default)]
+struct PartialDocument {
+    id: Option<String>,
+    title: Option<String>,
+    url: Option<String>,
+    text: Option<String>,
+    error: Option<CercamiError>,
+}
+
+impl PartialDocument {
+    fn field(&mut self, elements: &XmlElements, name: &[u8]) -> Option<&mut Option<String>> {
+        if name == elements.id.as_bytes() {
+            Some(&mut self.id)
+        } else if name == elements.title.as_bytes() {
+            Some(&mut self.title)
+        } else if name == elements.url.as_bytes() {
+            Some(&mut self.url)
+        } else if name == elements.text.as_bytes() {
+            Some(&mut self.text)
+        } else {
+            None
+        }
+    }
+
+    fn finish(self, elements: &XmlElements) -> Result<(Document, Option<u32>), CercamiError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        let id = self
+            .id
+            .map(|id| {
+                id.trim()
+                    .parse()
+                    .map_err(|_| CercamiError::InvalidInput(format!("invalid document id `{id}`")))
+            })
+            .transpose()?;
+        // Fields are trimmed once here rather than per text event, so text
+        // split by CDATA or a comment keeps its inner whitespace.
+        let field = |value: Option<String>, name: &str| {
+            value
+                .map(|value| value.trim().to_string())
+                .ok_or_else(|| missing(name))
+        };
+        let doc = Document {
+            title: field(self.title, &elements.title)?,
+            url: field(self.url, &elements.url)?,
+            text: field(self.text, &elements.text)?,
+            id: 0,
+        };
+        Ok((doc, id))
+    }
+}
+
+/// Reads document elements one at a time, so only the document being parsed
+/// is held in memory. `visit` gets every document with its `<id>`, if any, or
+/// the error that made it unusable, such as a missing field; errors in the
+/// XML structure itself, including a missing root element or a truncated
+/// file, stop the read. Returns the number of document elements read.
+fn read_xml<R: BufRead>(
+    reader: R,
+    elements: &XmlElements,
+    mut visit: impl FnMut(Result<(Document, Option<u32>), CercamiError>) -> Result<(), CercamiError>,
+) -> Result<usize, CercamiError> {
+    let mut reader = Reader::from_reader(reader);
+    reader.expand_empty_elements(true).check_end_names(true);
+
+    let mut buf = Vec::new();
+    let mut doc: Option<PartialDocument> = None;
+    let mut field: Option<Vec<u8>> = None;
+    let mut depth = 0;
+    let mut open: Vec<Vec<u8>> = Vec::new();
+    let mut root_seen = false;
+    let mut documents = 0;
+
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(element) => {
+                root_seen = true;
+                open.push(element.name().to_vec());
+                if let Some(doc) = doc.as_mut() {
+                    depth += 1;
+                    if depth == 1 {
+                        if let Some(value) = doc.field(elements, element.name()) {
+                            value.get_or_insert_with(String::new);
+                            field = Some(element.name().to_vec());
+                        }
+                    }
+                } else if element.name() == elements.document.as_bytes() {
+                    doc = Some(PartialDocument::default());
+                    depth = 0;
+                }
+            }
+            Event::End(_) => match (open.pop(), doc.as_mut()) {
+                (_, Some(_)) if depth == 0 => {
+                    if let Some(finished) = doc.take() {
+                        documents += 1;
+                        visit(finished.finish(elements))?;
+                    }
+                }
+                (_, Some(_)) => {
+                    if depth == 1 {
+                        field = None;
+                    }
+                    depth -= 1;
+                }
+                (_, None) => {}
+            },
+            Event::Text(text) | Event::CData(text) if open.is_empty() => {
+                let text = String::from_utf8_lossy(&text);
+                let text = text.trim();
+                if !text.is_empty() {
+                    let token: String = text.chars().take(32).collect();
+                    return Err(quick_xml::Error::UnexpectedToken(token).into());
+                }
+            }
+            Event::Text(text) | Event::CData(text) => {
+                if let (Some(doc), Some(name), 1) = (doc.as_mut(), field.as_ref(), depth) {
+                    match text.unescape_and_decode(&reader) {
+                        Ok(decoded) => {
+                            if let Some(Some(value)) = doc.field(elements, name) {
+                                value.push_str(&decoded);
+                            }
+                        }
+                        Err(err) => {
+                            doc.error.get_or_insert(CercamiError::Xml(err));
+                        }
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    if let Some(element) = open.last() {
+        let element = format!("<{}>", String::from_utf8_lossy(element));
+        return Err(quick_xml::Error::UnexpectedEof(element).into());
+    }
+    if !root_seen {
+        return Err(quick_xml::Error::UnexpectedEof("the root element".to_string()).into());
+    }
+
+    Ok(documents)
+}
+
+fn missing(field: &str) -> CercamiError {
+    CercamiError::InvalidInput(format!("missing field `{field}`"))
+}
+
+/// Finds the files under `dir` with `extension`. Symlinked directories are
+/// not followed, so a link loop cannot recurse forever, and subdirectories
+/// that cannot be read are added to `skipped`.
+fn collect_files(
+    dir: &Path,
+    extension: &str,
+    files: &mut Vec<PathBuf>,
+    skipped: &mut Vec<String>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_dir() {
+            if let Err(err) = collect_files(&path, extension, files, skipped) {
+                skipped.push(format!("{}: {err}", path.display()));
+            }
+        } else if path.extension().is_some_and(|ext| ext == extension) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Visited = Vec<Result<Document, CercamiError>>;
+
+    fn read(xml: &str) -> Result<(usize, Visited), CercamiError> {
+        let mut visited = Vec::new();
+        let count = read_xml(xml.as_bytes(), &XmlElements::default(), |doc| {
+            visited.push(doc.map(|(doc, _)| doc));
+            Ok(())
+        })?;
+        Ok((count, visited))
+    }
+
+    fn doc(title: &str, text: &str) -> String {
+        format!("<doc><title>{title}</title><url>u</url><abstract>{text}</abstract></doc>")
+    }
+
+    /// A file under the temp directory, unique to this process, removed on
+    /// drop even if the test fails.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("cercami-{}-{name}", std::process::id()));
+            fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn reads_documents_and_ignores_nested_elements() {
+        let xml = format!(
+            "<feed>{}<doc><title>B</title><url>u</url><abstract>x <b>bold</b> y</abstract>\
+             <links><sublink><title>inner</title></sublink></links></doc></feed>",
+            doc("A", " a &amp; b "),
+        );
+        let (count, visited) = read(&xml).unwrap();
+        assert_eq!(count, 2);
+
+        let docs: Vec<Document> = visited.into_iter().map(Result::unwrap).collect();
+        assert_eq!(docs[0].title, "A");
+        assert_eq!(docs[0].text, "a & b");
+        assert_eq!(docs[1].title, "B");
+        assert_eq!(docs[1].text, "x  y");
+    }
+
+    #[test]
+    fn keeps_cdata_spacing_and_trims_fields() {
+        let (_, visited) =
+            read(&format!("<feed>{}</feed>", doc(" A ", "a<![CDATA[ b ]]>c"))).unwrap();
+        let doc = visited.into_iter().next().unwrap().unwrap();
+        assert_eq!(doc.title, "A");
+        assert_eq!(doc.text, "a b c");
+    }
+
+    #[test]
+    fn reports_documents_missing_fields() {
+        let (count, visited) = read("<feed><doc><title>A</title></doc></feed>").unwrap();
+        assert_eq!(count, 1);
+        assert!(matches!(&visited[..], [Err(CercamiError::InvalidInput(_))]));
+    }
+
+    #[test]
+    fn reads_empty_roots() {
+        assert_eq!(read("<feed></feed>").unwrap().0, 0);
+        assert_eq!(read("<?xml version=\"1.0\"?>\n<feed/>\n").unwrap().0, 0);
+    }
+
+    #[test]
+    fn rejects_empty_and_malformed_input() {
+        for xml in [
+            "",
+            "  \n",
+            "not xml at all",
+            "<feed><doc><title>A",
+            "<feed><doc></feed>",
+            "<feed></feed> trailing",
+        ] {
+            assert!(
+                matches!(read(xml), Err(CercamiError::Xml(_))),
+                "{:?} was accepted",
+                xml
+            );
+        }
+    }
+
+    #[test]
+    fn several_dumps_are_optimized_once() {
+        let dumps: Vec<String> = (0..2)
+            .map(|part| {
+                let docs: String = (0..200)
+                    .map(|id| doc(&format!("Part {part}"), &format!("common word{id}")))
+                    .collect();
+                format!("<feed>{docs}</feed>")
+            })
+            .collect();
+        let files: Vec<TempFile> = dumps
+            .iter()
+            .enumerate()
+            .map(|(part, xml)| TempFile::new(&format!("dump-{part}.xml"), xml))
+            .collect();
+        let paths: Vec<&str> = files.iter().map(TempFile::path).collect();
+
+        let mut unoptimized = Index::default();
+        for xml in &dumps {
+            let mut monitor = Monitor::default();
+            unoptimized
+                .read_dump(
+                    xml.as_bytes(),
+                    "input",
+                    &XmlElements::default(),
+                    &mut monitor,
+                )
+                .unwrap();
+        }
+
+        let index = Index::from_files(&paths).unwrap();
+        assert_eq!(index.stats().documents, 400);
+        assert_eq!(
+            index.stats().unoptimized_bitmap_bytes,
+            Some(unoptimized.bitmap_bytes())
+        );
+        assert!(index.stats().bitmap_bytes < unoptimized.bitmap_bytes());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directories_skip_link_loops_and_unreadable_subdirectories() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let root = std::env::temp_dir().join(format!("cercami-{}-dir", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let locked = root.join("locked");
+        fs::create_dir_all(&locked).unwrap();
+        fs::write(root.join("a.txt"), "ferris").unwrap();
+        fs::write(locked.join("b.txt"), "crab").unwrap();
+        symlink(&root, root.join("loop")).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions do not stop root, so only expect a skip if they apply.
+        let locked_out = fs::read_dir(&locked).is_err();
+
+        let index = Index::from_dir(root.to_str().unwrap());
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        let index = index.unwrap();
+        if locked_out {
+            assert_eq!(index.stats().documents, 1);
+            assert_eq!(index.stats().files_skipped, 1);
+            assert!(index.skipped()[0].contains("locked"));
+        } else {
+            assert_eq!(index.stats().documents, 2);
+            assert_eq!(index.stats().files_skipped, 0);
+        }
+    }
+}
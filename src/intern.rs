@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use rustc_hash::FxHashSet;
+
+/// Tokens shared by the maps of an index, so a token that appears in several
+/// of them, or is inserted many times, is allocated once.
+#[derive(Default)]
+pub struct Interner {
+    tokens: FxHashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, token: &str) -> Arc<str> {
+        if let Some(interned) = self.tokens.get(token) {
+            return Arc::clone(interned);
+        }
+
+        let interned: Arc<str> = Arc::from(token);
+        self.tokens.insert(Arc::clone(&interned));
+        interned
+    }
+
+    /// Forgets `token` if no map uses it any more.
+    pub fn release(&mut self, token: &str) {
+        if self
+            .tokens
+            .get(token)
+            .is_some_and(|interned| Arc::strong_count(interned) == 1)
+        {
+            self.tokens.remove(token);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tokens.clear();
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.tokens.iter().map(|token| token.len()).sum()
+    }
+}
@@ -1,299 +1,2553 @@
 #![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+// Almost every public method can fail with an I/O or input error, and
+// `CercamiError` already describes each case.
 #![allow(clippy::missing_errors_doc)]
 
-use std::collections::HashMap;
-use std::env;
-use std::error;
-use std::fs;
-use std::io;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::iter;
+use std::mem;
+use std::slice;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::time;
 
 use croaring::bitmap::Bitmap;
-use quick_xml::de::from_reader;
-use rust_stemmers::{Algorithm, Stemmer};
-use serde::Deserialize;
-
-const STOP_WORDS: [&str; 127] = [
-    "i",
-    "me",
-    "my",
-    "myself",
-    "we",
-    "our",
-    "ours",
-    "ourselves",
-    "you",
-    "your",
-    "yours",
-    "yourself",
-    "yourselves",
-    "he",
-    "him",
-    "his",
-    "himself",
-    "she",
-    "her",
-    "hers",
-    "herself",
-    "it",
-    "its",
-    "itself",
-    "they",
-    "them",
-    "their",
-    "theirs",
-    "themselves",
-    "what",
-    "which",
-    "who",
-    "whom",
-    "this",
-    "that",
-    "these",
-    "those",
-    "am",
-    "is",
-    "are",
-    "was",
-    "were",
-    "be",
-    "been",
-    "being",
-    "have",
-    "has",
-    "had",
-    "having",
-    "do",
-    "does",
-    "did",
-    "doing",
-    "a",
-    "an",
-    "the",
-    "and",
-    "but",
-    "if",
-    "or",
-    "because",
-    "as",
-    "until",
-    "while",
-    "of",
-    "at",
-    "by",
-    "for",
-    "with",
-    "about",
-    "against",
-    "between",
-    "into",
-    "through",
-    "during",
-    "before",
-    "after",
-    "above",
-    "below",
-    "to",
-    "from",
-    "up",
-    "down",
-    "in",
-    "out",
-    "on",
-    "off",
-    "over",
-    "under",
-    "again",
-    "further",
-    "then",
-    "once",
-    "here",
-    "there",
-    "when",
-    "where",
-    "why",
-    "how",
-    "all",
-    "any",
-    "both",
-    "each",
-    "few",
-    "more",
-    "most",
-    "other",
-    "some",
-    "such",
-    "no",
-    "nor",
-    "not",
-    "only",
-    "own",
-    "same",
-    "so",
-    "than",
-    "too",
-    "very",
-    "s",
-    "t",
-    "can",
-    "will",
-    "just",
-    "don",
-    "should",
-    "now",
-];
-
-pub fn run(config: &Config) -> Result<(), Box<dyn error::Error>> {
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use regex::RegexBuilder;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+mod builder;
+mod cache;
+mod error;
+mod input;
+mod intern;
+mod persist;
+mod query;
+mod tokenizer;
+#[cfg(feature = "watch")]
+mod watch;
+
+pub use builder::IndexBuilder;
+use cache::QueryCache;
+pub use error::CercamiError;
+use input::Monitor;
+pub use input::{CsvColumns, XmlElements};
+use intern::Interner;
+use query::Expr;
+pub use query::ParseError;
+pub use rust_stemmers::Algorithm;
+pub use tokenizer::{ApostrophePolicy, DefaultTokenizer, Normalizer, Tokenizer, TokenizerSettings};
+#[cfg(feature = "watch")]
+pub use watch::WatchHandle;
+
+const MAX_FUZZY_EXPANSIONS: usize = 64;
+
+const SEARCH_ITER_BLOCK: usize = 256;
+
+const DID_YOU_MEAN_DISTANCE: usize = 2;
+
+pub const DEFAULT_NGRAM_SIZE: usize = 3;
+
+pub const DEFAULT_LIMIT: usize = 10;
+
+const SNIPPET_RADIUS: usize = 40;
+
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// Queries with at least this many terms are matched in parallel.
+#[cfg(feature = "parallel")]
+const PARALLEL_SEARCH_TERMS: usize = 8;
+
+pub fn run(config: &Config) -> Result<(), CercamiError> {
+    run_with_writer(config, &mut io::stdout().lock())
+}
+
+/// Like `run`, but writes the results to `writer`. The statistics follow the
+/// results, except in JSON mode where they go to stderr.
+pub fn run_with_writer<W: Write>(config: &Config, writer: &mut W) -> Result<(), CercamiError> {
+    if config.stream {
+        return stream_with_writer(config, writer);
+    }
+
+    let outcome = execute(config)?;
+    report_skipped(&outcome.skipped)?;
+
+    if config.json {
+        let output: Vec<OutputResult> = outcome
+            .results
+            .iter()
+            .map(|hit| OutputResult {
+                id: hit.id,
+                title: &hit.document.title,
+                url: &hit.document.url,
+                snippet: hit.snippet.as_deref(),
+            })
+            .collect();
+        writeln!(writer, "{}", serde_json::to_string_pretty(&output)?)?;
+    } else {
+        for hit in &outcome.results {
+            writeln!(writer, "{} {}", hit.id, hit.document.title)?;
+            writeln!(writer, "    {}", hit.document.url)?;
+            writeln!(writer, "    {}", hit.document.text)?;
+        }
+    }
+
+    let mut stderr = io::stderr();
+    let out: &mut dyn Write = if config.json { &mut stderr } else { writer };
+    write_stats(
+        out,
+        format_args!("Number of results: {}", outcome.total),
+        &outcome.stats,
+        outcome.indexing_time,
+        outcome.search_time,
+    )
+}
+
+/// Writes each result as soon as it is found, in id order, flushing after
+/// every one; JSON results are written one object per line. Stops after
+/// `config.limit` results without looking at the rest.
+fn stream_with_writer<W: Write>(config: &Config, writer: &mut W) -> Result<(), CercamiError> {
     let index_start = time::Instant::now();
-    let index = Index::new(&config.db_path)?;
-    let indexing_time = index_start.elapsed().as_secs();
+    let db_paths: Vec<&str> = config.db_paths.iter().map(String::as_str).collect();
+    let index = Index::from_files(&db_paths)?;
+    let indexing_time = index_start.elapsed();
+    report_skipped(index.skipped())?;
 
     let search_start = time::Instant::now();
-    let results = index.search(&config.query);
-    let search_time = search_start.elapsed().as_micros();
+    let mut written = 0;
+    let mut result = Ok(());
+    if config.limit > 0 {
+        index.search_each(&config.query, |id, document| {
+            result = write_hit(config, &index, writer, id, document);
+            if result.is_err() {
+                return false;
+            }
+            written += 1;
+            written < config.limit
+        });
+    }
+    result?;
+    let search_time = search_start.elapsed();
 
-    for result in results.iter() {
-        println!("{} {}", result, index.documents.get(&result).unwrap());
+    let mut stderr = io::stderr();
+    let out: &mut dyn Write = if config.json { &mut stderr } else { writer };
+    write_stats(
+        out,
+        format_args!("Number of results streamed: {written}"),
+        &index.stats(),
+        indexing_time,
+        search_time,
+    )
+}
+
+fn report_skipped(skipped: &[String]) -> Result<(), CercamiError> {
+    let mut stderr = io::stderr();
+    for reason in skipped {
+        writeln!(stderr, "Skipping {reason}")?;
     }
+    Ok(())
+}
 
-    println!("Number of results: {}", results.cardinality());
-    println!(
+fn write_stats(
+    out: &mut dyn Write,
+    results: fmt::Arguments<'_>,
+    stats: &IndexStats,
+    indexing_time: time::Duration,
+    search_time: time::Duration,
+) -> Result<(), CercamiError> {
+    writeln!(out, "{results}")?;
+    writeln!(
+        out,
         "Total number of indexed documents: {}",
-        index.documents.len()
-    );
-    println!("Total number of indexed tokens: {}", index.index.len());
-    println!("Indexing: {}s", indexing_time);
-    println!("Search: {}\u{3bc}s", search_time);
+        stats.documents
+    )?;
+    writeln!(out, "Total number of indexed tokens: {}", stats.tokens)?;
+    writeln!(out, "Indexing: {}s", indexing_time.as_secs())?;
+    writeln!(out, "Search: {}\u{3bc}s", search_time.as_micros())?;
+    Ok(())
+}
+
+fn write_hit<W: Write>(
+    config: &Config,
+    index: &Index,
+    writer: &mut W,
+    id: u32,
+    document: &StoredDocument,
+) -> Result<(), CercamiError> {
+    if config.json {
+        let snippet = index.snippet(id, &config.query, SNIPPET_RADIUS);
+        let output = OutputResult {
+            id,
+            title: &document.title,
+            url: &document.url,
+            snippet: snippet.as_deref(),
+        };
+        writeln!(writer, "{}", serde_json::to_string(&output)?)?;
+    } else {
+        writeln!(writer, "{id} {}", document.title)?;
+        writeln!(writer, "    {}", document.url)?;
+        writeln!(writer, "    {}", document.text)?;
+    }
+    writer.flush()?;
     Ok(())
 }
 
+/// Builds the index and runs the query described by `config`, returning up
+/// to `config.limit` results without printing anything.
+pub fn execute(config: &Config) -> Result<SearchOutcome, CercamiError> {
+    let index_start = time::Instant::now();
+    let db_paths: Vec<&str> = config.db_paths.iter().map(String::as_str).collect();
+    let index = Index::from_files(&db_paths)?;
+    let indexing_time = index_start.elapsed();
+
+    let search_start = time::Instant::now();
+    let results = index.search_ordered(&config.query);
+    let search_time = search_start.elapsed();
+
+    let hits = results
+        .iter()
+        .copied()
+        .take(config.limit)
+        .filter_map(|id| {
+            index.documents.get(&id).map(|document| SearchHit {
+                id,
+                document: document.clone(),
+                snippet: index.snippet(id, &config.query, SNIPPET_RADIUS),
+            })
+        })
+        .collect();
+
+    Ok(SearchOutcome {
+        results: hits,
+        total: results.len() as u64,
+        stats: index.stats(),
+        skipped: index.skipped,
+        indexing_time,
+        search_time,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchOutcome {
+    pub results: Vec<SearchHit>,
+    pub total: u64,
+    pub stats: IndexStats,
+    /// See `Index::skipped`.
+    pub skipped: Vec<String>,
+    pub indexing_time: time::Duration,
+    pub search_time: time::Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub id: u32,
+    pub document: StoredDocument,
+    pub snippet: Option<String>,
+}
+
+#[derive(Serialize)]
+struct OutputResult<'a> {
+    id: u32,
+    title: &'a str,
+    url: &'a str,
+    snippet: Option<&'a str>,
+}
+
 pub struct Config {
     pub query: String,
-    pub db_path: String,
+    pub db_paths: Vec<String>,
+    pub limit: usize,
+    pub json: bool,
+    pub stream: bool,
 }
 
 impl Config {
-    pub fn new(mut args: env::Args) -> Result<Self, &'static str> {
+    /// Parses `cercami [--limit N] [--json] [--stream] <db_path>... <query>`
+    /// from the program name and arguments, as given by `env::args`. A query
+    /// of `-` is read from stdin instead.
+    pub fn new<I>(args: I) -> Result<Self, CercamiError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut args = args.into_iter();
         args.next();
 
-        let db_path = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a db path"),
-        };
+        let mut limit = DEFAULT_LIMIT;
+        let mut json = false;
+        let mut stream = false;
+        let mut positional = Vec::new();
+
+        while let Some(arg) = args.next() {
+            if arg == "--limit" {
+                let Some(value) = args.next() else {
+                    return Err(CercamiError::Config("--limit needs a value".to_string()));
+                };
+                limit = parse_limit(&value)?;
+            } else if let Some(value) = arg.strip_prefix("--limit=") {
+                limit = parse_limit(value)?;
+            } else if arg == "--json" {
+                json = true;
+            } else if arg == "--stream" {
+                stream = true;
+            } else if arg.starts_with("--") {
+                return Err(CercamiError::Config(format!("Unknown option {arg}")));
+            } else {
+                positional.push(arg);
+            }
+        }
+
+        if positional.is_empty() {
+            return Err(CercamiError::Config("Didn't get a db path".to_string()));
+        }
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query"),
+        let Some(query) = positional.pop().filter(|_| !positional.is_empty()) else {
+            return Err(CercamiError::Config("Didn't get a query".to_string()));
+        };
+        let db_paths = positional;
+        let query = if query == "-" {
+            let mut query = String::new();
+            io::stdin().read_to_string(&mut query)?;
+            query.trim().to_string()
+        } else {
+            query
         };
 
-        Ok(Self { query, db_path })
+        Ok(Self {
+            query,
+            db_paths,
+            limit,
+            json,
+            stream,
+        })
     }
 }
 
+fn parse_limit(value: &str) -> Result<usize, CercamiError> {
+    value
+        .parse()
+        .map_err(|_| CercamiError::Config(format!("Invalid --limit value `{value}`")))
+}
+
+type Token = Arc<str>;
+
+// The maps on the `add` and `search` paths use FxHash: their keys are
+// tokens and document ids, not attacker-chosen data, so SipHash's protection
+// against collisions is not worth its cost. Iteration order differs from
+// `std::collections::HashMap`.
+//
+// `index` is the inverted index itself and the other `*_index` fields mirror
+// its name, so clippy's complaint about field names repeating `Index` is
+// silenced here rather than renaming them.
+#[allow(clippy::struct_field_names)]
 pub struct Index {
-    index: HashMap<String, Bitmap>,
-    documents: HashMap<u32, String>,
-    stemmer: Stemmer,
+    index: FxHashMap<Token, Bitmap>,
+    title_index: FxHashMap<Token, Bitmap>,
+    ngram_index: FxHashMap<Token, Bitmap>,
+    positions: FxHashMap<Token, HashMap<u32, Vec<u32>>>,
+    tokens: Interner,
+    lengths: FxHashMap<u32, u32>,
+    total_length: u64,
+    documents: FxHashMap<u32, StoredDocument>,
+    synonyms: HashMap<String, Vec<String>>,
+    dedup: bool,
+    content_hashes: HashMap<u64, Vec<u32>>,
+    duplicates_skipped: u64,
+    strict: bool,
+    malformed_skipped: u64,
+    blank_lines_skipped: u64,
+    files_skipped: u64,
+    skipped: Vec<String>,
+    unoptimized_bitmap_bytes: Option<usize>,
+    tokenizer: Box<dyn Tokenizer>,
+    ngram_size: Option<usize>,
+    bm25: Bm25,
+    field_boosts: FieldBoosts,
+    default_operator: DefaultOperator,
+    skip_missing: bool,
+    cache: Option<Mutex<QueryCache>>,
 }
 
-impl Index {
-    pub fn new(db_path: &str) -> Result<Self, Box<dyn error::Error>> {
-        let file = fs::File::open(db_path)?;
-        let reader = io::BufReader::new(file);
-        let docs: Docs = from_reader(reader)?;
+// An `Arc<Index>` must be able to serve searches from several threads.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Index>();
+};
 
-        let index = HashMap::new();
-        let stemmer = Stemmer::create(Algorithm::English);
-        let documents = HashMap::new();
+#[derive(Clone, Copy)]
+struct Idf {
+    plain: f64,
+    bm25: f64,
+}
 
-        let mut index = Self {
+impl Idf {
+    fn new(frequency: f64, total: f64) -> Self {
+        Self {
+            plain: (total / frequency).ln(),
+            bm25: ((total - frequency + 0.5) / (frequency + 0.5)).ln_1p(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Bm25 {
+    pub k1: f64,
+    pub b: f64,
+}
+
+impl Default for Bm25 {
+    fn default() -> Self {
+        Self { k1: 1.2, b: 0.75 }
+    }
+}
+
+/// Weights applied to each field's contribution in `search_ranked` and
+/// `search_tfidf`. A title match counts as a single occurrence of the term.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct FieldBoosts {
+    pub title: f64,
+    pub text: f64,
+}
+
+impl Default for FieldBoosts {
+    fn default() -> Self {
+        Self {
+            title: 2.0,
+            text: 1.0,
+        }
+    }
+}
+
+/// How `search` joins whitespace-separated terms.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum DefaultOperator {
+    #[default]
+    And,
+    Or,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        let index = FxHashMap::default();
+        let title_index = FxHashMap::default();
+        let ngram_index = FxHashMap::default();
+        let positions = FxHashMap::default();
+        let lengths = FxHashMap::default();
+        let documents = FxHashMap::default();
+
+        Self {
             index,
-            stemmer,
+            title_index,
+            ngram_index,
+            positions,
+            tokens: Interner::default(),
+            lengths,
+            total_length: 0,
             documents,
-        };
+            synonyms: HashMap::new(),
+            dedup: false,
+            content_hashes: HashMap::new(),
+            duplicates_skipped: 0,
+            strict: true,
+            malformed_skipped: 0,
+            blank_lines_skipped: 0,
+            files_skipped: 0,
+            skipped: Vec::new(),
+            unoptimized_bitmap_bytes: None,
+            tokenizer: Box::new(DefaultTokenizer::default()),
+            ngram_size: None,
+            bm25: Bm25::default(),
+            field_boosts: FieldBoosts::default(),
+            default_operator: DefaultOperator::default(),
+            skip_missing: false,
+            cache: None,
+        }
+    }
+}
 
-        for (idx, doc) in docs.doc.iter().enumerate() {
-            let document = Document {
-                title: doc.title.clone(),
-                id: idx as u32,
-                text: doc.text.clone(),
-                url: doc.url.clone(),
-            };
+impl Index {
+    #[must_use]
+    pub fn builder() -> IndexBuilder {
+        IndexBuilder::new()
+    }
+
+    pub fn new(db_path: &str) -> Result<Self, CercamiError> {
+        Self::from_files(&[db_path])
+    }
 
-            index.add(&document)
+    /// Like `new`, calling `progress` with the number of documents read so
+    /// far after every `every` documents.
+    pub fn with_progress<F>(
+        db_path: &str,
+        every: usize,
+        mut progress: F,
+    ) -> Result<Self, CercamiError>
+    where
+        F: FnMut(usize),
+    {
+        if every == 0 {
+            return Err(CercamiError::Config(
+                "progress interval must be at least 1".to_string(),
+            ));
         }
 
+        let mut index = Self::default();
+        index.index_file_monitored(
+            db_path,
+            &mut Monitor {
+                every,
+                progress: Some(&mut progress),
+                cancel: None,
+            },
+        )?;
+        Ok(index)
+    }
+
+    /// Indexes several XML dumps as one corpus, numbering the documents of
+    /// each file after those of the previous ones.
+    pub fn from_files(db_paths: &[&str]) -> Result<Self, CercamiError> {
+        let mut index = Self::default();
+        index.index_files(db_paths)?;
+        Ok(index)
+    }
+
+    pub fn with_tokenizer<T>(db_path: &str, tokenizer: T) -> Result<Self, CercamiError>
+    where
+        T: Tokenizer + 'static,
+    {
+        let mut index = Self {
+            tokenizer: Box::new(tokenizer),
+            ..Self::default()
+        };
+        index.index_file(db_path)?;
+        Ok(index)
+    }
+
+    pub fn with_stop_words(db_path: &str, stop_words: Vec<String>) -> Result<Self, CercamiError> {
+        Self::with_tokenizer(db_path, DefaultTokenizer::default().stop_words(stop_words))
+    }
+
+    pub fn without_stemming(db_path: &str) -> Result<Self, CercamiError> {
+        Self::with_tokenizer(db_path, DefaultTokenizer::default().stemming(false))
+    }
+
+    pub fn with_diacritic_folding(db_path: &str) -> Result<Self, CercamiError> {
+        Self::with_tokenizer(db_path, DefaultTokenizer::default().fold_diacritics(true))
+    }
+
+    pub fn with_ngrams(db_path: &str, size: usize) -> Result<Self, CercamiError> {
+        let mut index = Self {
+            ngram_size: Some(size.max(1)),
+            ..Self::default()
+        };
+        index.index_file(db_path)?;
         Ok(index)
     }
 
+    pub fn with_token_length(
+        db_path: &str,
+        min_token_len: usize,
+        max_token_len: usize,
+    ) -> Result<Self, CercamiError> {
+        Self::with_tokenizer(
+            db_path,
+            DefaultTokenizer::default().token_length(min_token_len, max_token_len),
+        )
+    }
+
+    pub fn new_with_language(db_path: &str, language: Algorithm) -> Result<Self, CercamiError> {
+        Self::with_tokenizer(db_path, DefaultTokenizer::new(language))
+    }
+
+    /// Unqualified terms match a document's title as well as its text, while
+    /// `title:` and `text:` restrict a term to one field.
+    ///
+    /// A query without any searchable term, such as an empty or all-stop-word
+    /// one, matches every document; a query whose terms match nothing, or
+    /// that only excludes terms, returns an empty bitmap.
+    ///
+    /// With a query cache enabled, repeated queries are answered from it.
+    #[must_use]
     pub fn search(&self, query: &str) -> Bitmap {
-        let tokens = self.tokenize(query);
-        let mut results = Bitmap::create();
+        let Some(cache) = &self.cache else {
+            return self.search_uncached(query);
+        };
 
-        for token in tokens {
-            match self.index.get(&token) {
-                Some(indexes) => {
-                    results = match results.cardinality() {
-                        0 => indexes.clone(),
-                        _ => results.and(indexes),
-                    };
+        let key = QueryCache::key(query);
+        let cached = lock(cache).get(&key);
+        if let Some(results) = cached {
+            return results;
+        }
+
+        let results = self.search_uncached(query);
+        lock(cache).insert(key, results.clone());
+        results
+    }
+
+    /// Keeps the results of up to `capacity` distinct queries, evicting the
+    /// least recently used. Any change to the index clears it; a capacity of
+    /// 0 disables the cache.
+    pub fn set_query_cache(&mut self, capacity: usize) {
+        self.cache = (capacity > 0).then(|| Mutex::new(QueryCache::new(capacity)));
+    }
+
+    fn invalidate_cache(&mut self) {
+        if let Some(cache) = &mut self.cache {
+            cache
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+        }
+    }
+
+    fn search_uncached(&self, query: &str) -> Bitmap {
+        let (terms, excluded) = self.split_exclusions(query);
+
+        #[cfg(feature = "parallel")]
+        let results = if terms.len() >= PARALLEL_SEARCH_TERMS {
+            self.match_terms_parallel(&terms)
+        } else {
+            self.match_terms(&terms)
+        };
+        #[cfg(not(feature = "parallel"))]
+        let results = self.match_terms(&terms);
+
+        self.exclude(results, excluded.as_ref())
+    }
+
+    /// Splits the `-term` exclusions off `query`, returning the other terms
+    /// and the documents matching any exclusion, or `None` when there are
+    /// no exclusions.
+    fn split_exclusions<'a>(&self, query: &'a str) -> (Vec<&'a str>, Option<Bitmap>) {
+        let mut terms = Vec::new();
+        let mut excluded: Option<Bitmap> = None;
+
+        for term in query.split_whitespace() {
+            if let Some(term) = term.strip_prefix('-') {
+                let excluded = excluded.get_or_insert_with(Bitmap::create);
+                let (fields, term) = self.qualify(term);
+                for token in self.tokenize(term) {
+                    for index in &fields {
+                        if let Some(indexes) = index.get(token.as_str()) {
+                            excluded.or_inplace(indexes);
+                        }
+                    }
+                }
+            } else {
+                terms.push(term);
+            }
+        }
+
+        (terms, excluded)
+    }
+
+    /// Removes `excluded` from `results`. A query without searchable terms
+    /// matches everything, unless it only excludes.
+    fn exclude(&self, results: Option<Bitmap>, excluded: Option<&Bitmap>) -> Bitmap {
+        match (results, excluded) {
+            (Some(mut results), Some(excluded)) => {
+                results.andnot_inplace(excluded);
+                results
+            }
+            (Some(results), None) => results,
+            (None, Some(_)) => Bitmap::create(),
+            (None, None) => self.match_all(),
+        }
+    }
+
+    /// Combines the matches of every term with the default operator, or
+    /// `None` when no term has searchable tokens.
+    fn match_terms(&self, terms: &[&str]) -> Option<Bitmap> {
+        let mut results: Option<Bitmap> = None;
+        let mut missing = false;
+
+        for term in terms {
+            let Some(matches) = self.match_term(term) else {
+                continue;
+            };
+            if matches.is_empty() && self.default_operator == DefaultOperator::And {
+                if self.skip_missing {
+                    missing = true;
+                    continue;
+                }
+                return Some(matches);
+            }
+
+            results = Some(match results {
+                None => matches,
+                Some(results) => self.combine(&results, &matches),
+            });
+        }
+
+        results.or_else(|| missing.then(Bitmap::create))
+    }
+
+    /// Like `match_terms`, but looks the terms up on the rayon pool and
+    /// combines them in a balanced reduction tree.
+    #[cfg(feature = "parallel")]
+    fn match_terms_parallel(&self, terms: &[&str]) -> Option<Bitmap> {
+        let mut matches: Vec<Bitmap> = terms
+            .par_iter()
+            .filter_map(|term| self.match_term(term))
+            .collect();
+        if matches.is_empty() {
+            return None;
+        }
+
+        if self.skip_missing && self.default_operator == DefaultOperator::And {
+            matches.retain(|docs| !docs.is_empty());
+        }
+        Some(
+            matches
+                .into_par_iter()
+                .reduce_with(|a, b| self.combine(&a, &b))
+                .unwrap_or_else(Bitmap::create),
+        )
+    }
+
+    fn match_term(&self, term: &str) -> Option<Bitmap> {
+        let (fields, term) = self.qualify(term);
+        self.match_tokens(&fields, &self.tokenize(term))
+    }
+
+    fn combine(&self, a: &Bitmap, b: &Bitmap) -> Bitmap {
+        match self.default_operator {
+            DefaultOperator::And => a.and(b),
+            DefaultOperator::Or => a.or(b),
+        }
+    }
+
+    /// Same as `search(query).cardinality()`. With the AND operator the
+    /// smallest bitmaps are intersected first and the last step only counts,
+    /// without allocating the result. A token found in a single field is
+    /// used in place; one found in several fields, or with synonyms, is
+    /// matched into a new bitmap first.
+    #[must_use]
+    pub fn count(&self, query: &str) -> u64 {
+        if self.default_operator == DefaultOperator::Or || self.skip_missing {
+            return self.search(query).cardinality();
+        }
+
+        let (terms, excluded) = self.split_exclusions(query);
+        let mut bitmaps: Vec<Cow<'_, Bitmap>> = Vec::new();
+        for term in terms {
+            let (fields, term) = self.qualify(term);
+            for token in self.tokenize(term) {
+                let found: Vec<&Bitmap> = fields
+                    .iter()
+                    .filter_map(|index| index.get(token.as_str()))
+                    .collect();
+                let docs = match found.as_slice() {
+                    [docs] if !self.synonyms.contains_key(&token) => Cow::Borrowed(*docs),
+                    _ => Cow::Owned(
+                        self.match_tokens(&fields, slice::from_ref(&token))
+                            .unwrap_or_else(Bitmap::create),
+                    ),
+                };
+                if docs.is_empty() {
+                    return 0;
+                }
+                bitmaps.push(docs);
+            }
+        }
+
+        bitmaps.sort_unstable_by_key(|docs| docs.cardinality());
+        match (bitmaps.as_slice(), excluded) {
+            ([], None) => self.documents.len() as u64,
+            ([], Some(_)) => 0,
+            ([only], None) => only.cardinality(),
+            ([only], Some(excluded)) => only.andnot_cardinality(&excluded),
+            ([first, middle @ .., last], excluded) => {
+                let mut results = first.clone().into_owned();
+                for docs in middle {
+                    results.and_inplace(docs);
+                }
+                match excluded {
+                    None => results.and_cardinality(last),
+                    Some(excluded) => {
+                        results.and_inplace(last);
+                        results.andnot_cardinality(&excluded)
+                    }
                 }
-                None => return Bitmap::create(),
             }
         }
+    }
+
+    #[must_use]
+    pub fn search_field(&self, field: &str, query: &str) -> Bitmap {
+        self.field_index(field)
+            .and_then(|index| self.match_tokens(&[index], &self.tokenize(query)))
+            .unwrap_or_else(Bitmap::create)
+    }
+
+    fn field_index(&self, field: &str) -> Option<&FxHashMap<Token, Bitmap>> {
+        match field {
+            "title" => Some(&self.title_index),
+            "text" | "abstract" => Some(&self.index),
+            _ => None,
+        }
+    }
+
+    fn qualify<'a>(&self, term: &'a str) -> (Vec<&FxHashMap<Token, Bitmap>>, &'a str) {
+        term.split_once(':')
+            .and_then(|(field, term)| self.field_index(field).map(|index| (vec![index], term)))
+            .unwrap_or_else(|| (vec![&self.index, &self.title_index], term))
+    }
+
+    fn intersect(index: &FxHashMap<Token, Bitmap>, tokens: &[String]) -> Option<Bitmap> {
+        let mut results: Option<Bitmap> = None;
+
+        for token in tokens {
+            let Some(indexes) = index.get(token.as_str()) else {
+                return Some(Bitmap::create());
+            };
+
+            results = Some(results.map_or_else(|| indexes.clone(), |results| results.and(indexes)));
+        }
 
         results
     }
 
-    pub fn add(&mut self, doc: &Document) {
-        self.documents.insert(doc.id, doc.text.clone());
-        let tokens = self.tokenize(&doc.text);
+    /// Like `intersect`, but a token matches in any of `fields`, and also
+    /// matches its registered synonyms.
+    fn match_tokens(
+        &self,
+        fields: &[&FxHashMap<Token, Bitmap>],
+        tokens: &[String],
+    ) -> Option<Bitmap> {
+        let mut results: Option<Bitmap> = None;
 
         for token in tokens {
-            let docs_containing_token: Bitmap = if let Some(existing) = self.index.get(&token) {
-                if existing.contains(doc.id) {
-                    existing.clone()
-                } else {
-                    let mut tmp = existing.clone();
-                    tmp.add(doc.id);
-                    tmp
+            let mut matches = Bitmap::create();
+            let synonyms = self.synonyms.get(token).into_iter().flatten();
+            for token in std::iter::once(token).chain(synonyms) {
+                for index in fields {
+                    if let Some(indexes) = index.get(token.as_str()) {
+                        matches.or_inplace(indexes);
+                    }
                 }
-            } else {
-                let mut tmp = Bitmap::create();
-                tmp.add(doc.id);
-                tmp
+            }
+
+            if matches.is_empty() {
+                return Some(matches);
+            }
+
+            results = Some(match results {
+                None => matches,
+                Some(results) => results.and(&matches),
+            });
+        }
+
+        results
+    }
+
+    /// Makes `term` and each of `synonyms` match one another in `search`,
+    /// `search_field` and `search_boolean`. Nothing is expanded until
+    /// synonyms are registered.
+    pub fn add_synonyms(&mut self, term: &str, synonyms: Vec<String>) {
+        let Some(term) = self.tokenize(term).into_iter().next() else {
+            return;
+        };
+
+        for synonym in synonyms {
+            let Some(synonym) = self.tokenize(&synonym).into_iter().next() else {
+                continue;
             };
 
-            self.index.insert(token, docs_containing_token);
+            self.link_synonyms(&term, &synonym);
+            self.link_synonyms(&synonym, &term);
+        }
+    }
+
+    fn link_synonyms(&mut self, term: &str, synonym: &str) {
+        if term == synonym {
+            return;
+        }
+        self.invalidate_cache();
+
+        let synonyms = self.synonyms.entry(term.to_string()).or_default();
+        if !synonyms.iter().any(|existing| existing == synonym) {
+            synonyms.push(synonym.to_string());
+        }
+    }
+
+    pub fn search_boolean(&self, query: &str) -> Result<Bitmap, ParseError> {
+        if query.trim().is_empty() {
+            return Ok(Bitmap::create());
         }
+
+        let expr = query::parse(query)?;
+        Ok(self.evaluate(&expr).unwrap_or_else(Bitmap::create))
+    }
+
+    fn evaluate(&self, expr: &Expr) -> Option<Bitmap> {
+        match expr {
+            Expr::Term(term) => self.evaluate_term(term),
+            Expr::Near(a, b, distance) => Some(self.search_near(a, b, *distance)),
+            Expr::And(operands) => operands
+                .iter()
+                .fold(None, |left, right| match (left, right) {
+                    (Some(left), Expr::Not(excluded)) => Some(match self.evaluate(excluded) {
+                        Some(excluded) => left.andnot(&excluded),
+                        None => left,
+                    }),
+                    (left, right) => match (left, self.evaluate(right)) {
+                        (Some(left), Some(right)) => Some(left.and(&right)),
+                        (left, right) => left.or(right),
+                    },
+                }),
+            Expr::Or(operands) => {
+                operands
+                    .iter()
+                    .fold(None, |left, right| match (left, self.evaluate(right)) {
+                        (Some(left), Some(right)) => Some(left.or(&right)),
+                        (left, right) => left.or(right),
+                    })
+            }
+            Expr::Not(inner) => self
+                .evaluate(inner)
+                .map(|inner| self.match_all().andnot(&inner)),
+        }
+    }
+
+    fn evaluate_term(&self, term: &str) -> Option<Bitmap> {
+        let (fields, term) = self.qualify(term);
+        self.match_tokens(&fields, &self.tokenize(term))
+    }
+
+    #[must_use]
+    pub fn match_all(&self) -> Bitmap {
+        self.documents.keys().copied().collect()
+    }
+
+    /// Matches documents containing every character n-gram of the query.
+    /// Tokens shorter than the n-gram size are indexed and looked up whole.
+    #[must_use]
+    pub fn search_ngram(&self, query: &str) -> Bitmap {
+        let Some(size) = self.ngram_size else {
+            return Bitmap::create();
+        };
+
+        let grams: Vec<String> = self
+            .tokenize(query)
+            .iter()
+            .flat_map(|token| ngrams(token, size))
+            .collect();
+
+        Self::intersect(&self.ngram_index, &grams).unwrap_or_else(Bitmap::create)
+    }
+
+    /// Yields matching document ids in ascending order without collecting
+    /// them all into a `Vec`: ids are taken from the matches
+    /// `SEARCH_ITER_BLOCK` at a time.
+    pub fn search_iter(&self, query: &str) -> impl Iterator<Item = u32> + '_ {
+        let mut results = self.search(query);
+        iter::from_fn(move || {
+            let block: Vec<u32> = results.iter().take(SEARCH_ITER_BLOCK).collect();
+            let &last = block.last()?;
+            results.remove_range(0..u64::from(last) + 1);
+            Some(block)
+        })
+        .flatten()
     }
 
-    fn tokenize(&self, text: &str) -> Vec<String> {
-        text.to_lowercase()
+    /// Pairs each match of `query` with the query terms found in it, in query
+    /// order. Excluded terms and terms without searchable tokens are skipped.
+    #[must_use]
+    pub fn search_explain(&self, query: &str) -> Vec<(u32, Vec<String>)> {
+        let terms: Vec<(&str, Bitmap)> = query
             .split_whitespace()
-            .filter_map(|w| {
-                let word: String = w.chars().filter(|c| c.is_alphanumeric()).collect();
-                if STOP_WORDS.contains(&word.as_str()) {
-                    None
-                } else {
-                    Some(self.stemmer.stem(&word).into_owned())
-                }
+            .filter(|term| !term.starts_with('-'))
+            .filter_map(|term| self.match_term(term).map(|matches| (term, matches)))
+            .collect();
+
+        self.search(query)
+            .iter()
+            .map(|id| {
+                let matched = terms
+                    .iter()
+                    .filter(|(_, matches)| matches.contains(id))
+                    .map(|(term, _)| (*term).to_string())
+                    .collect();
+                (id, matched)
             })
             .collect()
     }
-}
 
-#[derive(Deserialize, Debug)]
-struct Docs {
-    doc: Vec<Document>,
-}
+    /// Calls `visit` with each document matching `query`, in id order, until
+    /// it returns `false`. The full set of matches is still computed up
+    /// front; stopping early only skips looking up and visiting the rest.
+    /// Returns how many documents were visited.
+    pub fn search_each<F>(&self, query: &str, mut visit: F) -> usize
+    where
+        F: FnMut(u32, &StoredDocument) -> bool,
+    {
+        let mut visited = 0;
+        for id in self.search(query).iter() {
+            let Some(document) = self.documents.get(&id) else {
+                continue;
+            };
+            visited += 1;
+            if !visit(id, document) {
+                break;
+            }
+        }
+        visited
+    }
 
-#[derive(Deserialize, Debug)]
-pub struct Document {
-    pub title: String,
-    pub url: String,
-    #[serde(rename = "abstract")]
-    pub text: String,
-    #[serde(skip)]
-    pub id: u32,
+    /// Matches of `query` ordered without scoring: documents containing more
+    /// of the query terms come first, then shorter documents, then lower ids.
+    #[must_use]
+    pub fn search_ordered(&self, query: &str) -> Vec<u32> {
+        let mut tokens = self.tokenize(query);
+        tokens.sort_unstable();
+        tokens.dedup();
+
+        let mut matches: Vec<(Reverse<usize>, u32, u32)> = self
+            .search(query)
+            .iter()
+            .map(|id| {
+                let matched = tokens
+                    .iter()
+                    .filter(|token| {
+                        [&self.index, &self.title_index].iter().any(|index| {
+                            index
+                                .get(token.as_str())
+                                .is_some_and(|docs| docs.contains(id))
+                        })
+                    })
+                    .count();
+                let length = self.lengths.get(&id).copied().unwrap_or(0);
+                (Reverse(matched), length, id)
+            })
+            .collect();
+        matches.sort_unstable();
+
+        matches.into_iter().map(|(_, _, id)| id).collect()
+    }
+
+    #[must_use]
+    pub fn search_documents(&self, query: &str) -> Vec<SearchResult> {
+        self.search(query)
+            .iter()
+            .filter_map(|id| {
+                self.documents.get(&id).map(|doc| SearchResult {
+                    id,
+                    title: doc.title.clone(),
+                    url: doc.url.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Buckets the documents matching `query` by the key `field_extractor`
+    /// derives from each of them.
+    #[must_use]
+    pub fn facet_counts(
+        &self,
+        query: &str,
+        field_extractor: impl Fn(&StoredDocument) -> String,
+    ) -> HashMap<String, u64> {
+        let mut counts = HashMap::new();
+
+        for id in self.search(query).iter() {
+            if let Some(doc) = self.documents.get(&id) {
+                *counts.entry(field_extractor(doc)).or_insert(0) += 1;
+            }
+        }
+
+        counts
+    }
+
+    /// Matches of `query` ordered by the key `key` derives from each stored
+    /// document. Equal keys keep ascending id order.
+    #[must_use]
+    pub fn search_sorted_by<K: Ord>(
+        &self,
+        query: &str,
+        key: impl Fn(&StoredDocument) -> K,
+    ) -> Vec<u32> {
+        let mut matches: Vec<(u32, &StoredDocument)> = self
+            .search(query)
+            .iter()
+            .filter_map(|id| self.documents.get(&id).map(|doc| (id, doc)))
+            .collect();
+        matches.sort_by_cached_key(|(_, doc)| key(doc));
+
+        matches.into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// Matches of `query` whose url host is `domain` or one of its
+    /// subdomains. Documents without a parseable url are left out.
+    #[must_use]
+    pub fn search_in_domain(&self, query: &str, domain: &str) -> Vec<u32> {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+
+        self.search(query)
+            .iter()
+            .filter(|id| {
+                self.documents
+                    .get(id)
+                    .and_then(|doc| url_host(&doc.url))
+                    .is_some_and(|host| {
+                        host == domain
+                            || host
+                                .strip_suffix(domain.as_str())
+                                .is_some_and(|subdomain| subdomain.ends_with('.'))
+                    })
+            })
+            .collect()
+    }
+
+    #[must_use]
+    pub fn search_paged(&self, query: &str, offset: usize, limit: usize) -> Page {
+        let results = self.search(query);
+
+        Page {
+            ids: results.iter().skip(offset).take(limit).collect(),
+            total: results.cardinality(),
+        }
+    }
+
+    #[must_use]
+    pub fn search_any(&self, query: &str) -> Bitmap {
+        let tokens = self.tokenize(query);
+        let mut results = Bitmap::create();
+
+        for token in tokens {
+            for index in [&self.index, &self.title_index] {
+                if let Some(indexes) = index.get(token.as_str()) {
+                    results.or_inplace(indexes);
+                }
+            }
+        }
+
+        results
+    }
+
+    /// Matches documents containing at least `min` of the distinct query
+    /// tokens: a `min` of 1 behaves like `search_any`, and a `min` equal to
+    /// the number of tokens like `search`.
+    #[must_use]
+    pub fn search_min_match(&self, query: &str, min: usize) -> Bitmap {
+        let mut tokens = self.tokenize(query);
+        tokens.sort_unstable();
+        tokens.dedup();
+
+        let fields = [&self.index, &self.title_index];
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for token in &tokens {
+            if let Some(indexes) = self.match_tokens(&fields, slice::from_ref(token)) {
+                for doc_id in indexes.iter() {
+                    *counts.entry(doc_id).or_insert(0) += 1;
+                }
+            }
+        }
+
+        counts
+            .into_iter()
+            .filter(|&(_, count)| count >= min.max(1))
+            .map(|(doc_id, _)| doc_id)
+            .collect()
+    }
+
+    /// Token positions are counted after stop-word removal, so stop words
+    /// inside a phrase are ignored on both the query and the document side.
+    #[must_use]
+    pub fn search_phrase(&self, phrase: &str) -> Bitmap {
+        let tokens = self.tokenize(phrase);
+        let Some(candidates) = Self::intersect(&self.index, &tokens) else {
+            return Bitmap::create();
+        };
+
+        if tokens.len() <= 1 || candidates.is_empty() {
+            return candidates;
+        }
+
+        let mut results = Bitmap::create();
+        for doc_id in candidates.iter() {
+            if self.contains_phrase(doc_id, &tokens) {
+                results.add(doc_id);
+            }
+        }
+
+        results
+    }
+
+    fn contains_phrase(&self, doc_id: u32, tokens: &[String]) -> bool {
+        let positions: Option<Vec<&Vec<u32>>> = tokens
+            .iter()
+            .map(|token| {
+                self.positions
+                    .get(token.as_str())
+                    .and_then(|docs| docs.get(&doc_id))
+            })
+            .collect();
+
+        let Some(positions) = positions else {
+            return false;
+        };
+
+        positions[0].iter().any(|&start| {
+            positions
+                .iter()
+                .zip(start..)
+                .skip(1)
+                .all(|(token_positions, position)| token_positions.binary_search(&position).is_ok())
+        })
+    }
+
+    /// Matches documents where `a` and `b` occur, in either order, at most
+    /// `distance` positions apart. As with phrases, positions are counted
+    /// after stop-word removal, so adjacent tokens are at distance 1.
+    #[must_use]
+    pub fn search_near(&self, a: &str, b: &str, distance: u32) -> Bitmap {
+        let (Some(a), Some(b)) = (
+            self.tokenize(a).into_iter().next(),
+            self.tokenize(b).into_iter().next(),
+        ) else {
+            return Bitmap::create();
+        };
+        let (Some(a_docs), Some(b_docs)) = (
+            self.positions.get(a.as_str()),
+            self.positions.get(b.as_str()),
+        ) else {
+            return Bitmap::create();
+        };
+
+        let mut results = Bitmap::create();
+        for (doc_id, a_positions) in a_docs {
+            let Some(b_positions) = b_docs.get(doc_id) else {
+                continue;
+            };
+
+            let near = a_positions.iter().any(|&pa| {
+                b_positions
+                    .iter()
+                    .any(|&pb| pa != pb && pa.abs_diff(pb) <= distance)
+            });
+            if near {
+                results.add(*doc_id);
+            }
+        }
+
+        results
+    }
+
+    /// Matches every indexed token starting with `prefix`. The prefix is
+    /// normalized like indexed words, see `Tokenizer::normalize_fragment`,
+    /// but not stemmed, and a trailing `*` is ignored. This is a linear scan
+    /// over the whole vocabulary.
+    #[must_use]
+    pub fn search_prefix(&self, prefix: &str) -> Bitmap {
+        let prefix = self.normalize_prefix(prefix);
+        let mut results = Bitmap::create();
+
+        if prefix.is_empty() {
+            return results;
+        }
+
+        for (token, indexes) in &self.index {
+            if token.starts_with(&prefix) {
+                results.or_inplace(indexes);
+            }
+        }
+
+        results
+    }
+
+    /// Documents containing a term with `fragment` anywhere in it, normalized
+    /// like `search_prefix` with `*` ignored at either end. Like
+    /// `search_prefix` this scans the whole vocabulary; at most
+    /// `max_expansions` matching terms are used, the alphabetically first.
+    #[must_use]
+    pub fn search_contains(&self, fragment: &str, max_expansions: usize) -> Bitmap {
+        let fragment = self.normalize_prefix(fragment.trim_start_matches('*'));
+        let mut results = Bitmap::create();
+
+        if fragment.is_empty() {
+            return results;
+        }
+
+        let mut terms: Vec<&Token> = self
+            .index
+            .keys()
+            .filter(|token| token.contains(&fragment))
+            .collect();
+        terms.sort_unstable();
+
+        for term in terms.into_iter().take(max_expansions) {
+            results.or_inplace(&self.index[term]);
+        }
+
+        results
+    }
+
+    /// Documents containing a term matched by `pattern`. Terms are matched in
+    /// their stored, stemmed form and the pattern is not anchored, so use
+    /// `^...$` to match whole terms. The compiled pattern is size-limited.
+    pub fn search_regex(&self, pattern: &str) -> Result<Bitmap, CercamiError> {
+        let regex = RegexBuilder::new(pattern)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .dfa_size_limit(REGEX_SIZE_LIMIT)
+            .build()?;
+        let mut results = Bitmap::create();
+
+        for (token, indexes) in &self.index {
+            if regex.is_match(token) {
+                results.or_inplace(indexes);
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Completes `prefix` to up to `max` indexed terms, the most frequent
+    /// first. Terms are stored stemmed, so suggestions are stems rather than
+    /// whole words. An empty prefix suggests the most frequent terms overall.
+    #[must_use]
+    pub fn suggest(&self, prefix: &str, max: usize) -> Vec<String> {
+        let prefix = self.normalize_prefix(prefix);
+
+        let mut terms: Vec<(&Token, u64)> = self
+            .index
+            .iter()
+            .filter(|(token, _)| token.starts_with(&prefix))
+            .map(|(token, docs)| (token, docs.cardinality()))
+            .collect();
+        terms.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        terms
+            .into_iter()
+            .take(max)
+            .map(|(token, _)| token.to_string())
+            .collect()
+    }
+
+    fn normalize_prefix(&self, prefix: &str) -> String {
+        self.tokenizer
+            .normalize_fragment(prefix.trim_end_matches('*'))
+    }
+
+    /// Like `search`, but a token with no exact match is expanded to the
+    /// indexed tokens within `max_distance` edits of it; `-term` exclusions
+    /// are matched exactly. Every indexed token is visited, so the cost grows
+    /// with the vocabulary; edit distances are only computed for tokens of a
+    /// compatible length, and at most `MAX_FUZZY_EXPANSIONS` of the closest
+    /// ones are used.
+    #[must_use]
+    pub fn search_fuzzy(&self, query: &str, max_distance: u8) -> Bitmap {
+        if max_distance == 0 {
+            return self.search(query);
+        }
+
+        let max_distance = usize::from(max_distance);
+        let (terms, excluded) = self.split_exclusions(query);
+        let mut results: Option<Bitmap> = None;
+
+        for term in terms {
+            let (fields, term) = self.qualify(term);
+            for token in self.tokenize(term) {
+                let matches = match self.match_tokens(&fields, slice::from_ref(&token)) {
+                    Some(matches) if !matches.is_empty() => matches,
+                    _ => Self::fuzzy_matches(&fields, &token, max_distance),
+                };
+
+                results = Some(match results {
+                    None => matches,
+                    Some(results) => results.and(&matches),
+                });
+            }
+        }
+
+        self.exclude(results, excluded.as_ref())
+    }
+
+    fn fuzzy_matches(
+        fields: &[&FxHashMap<Token, Bitmap>],
+        token: &str,
+        max_distance: usize,
+    ) -> Bitmap {
+        let mut candidates: Vec<(usize, &Token)> = fields
+            .iter()
+            .flat_map(|index| Self::fuzzy_candidates(index, token, max_distance))
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup_by(|a, b| a.1 == b.1);
+        candidates.truncate(MAX_FUZZY_EXPANSIONS);
+
+        let mut results = Bitmap::create();
+        for (_, key) in candidates {
+            for index in fields {
+                if let Some(indexes) = index.get(key) {
+                    results.or_inplace(indexes);
+                }
+            }
+        }
+
+        results
+    }
+
+    fn fuzzy_candidates<'a>(
+        index: &'a FxHashMap<Token, Bitmap>,
+        token: &str,
+        max_distance: usize,
+    ) -> Vec<(usize, &'a Token)> {
+        let length = token.chars().count();
+
+        index
+            .keys()
+            .filter(|key| key.chars().count().abs_diff(length) <= max_distance)
+            .filter_map(|key| levenshtein(token, key, max_distance).map(|d| (d, key)))
+            .collect()
+    }
+
+    /// Proposes a corrected query by replacing each word whose tokens are
+    /// missing from the index with the closest indexed term, preferring the
+    /// more frequent one on ties. Indexed terms are stemmed, so replacements
+    /// are stems. Returns `None` when every word is already indexed or no
+    /// term is close enough.
+    #[must_use]
+    pub fn did_you_mean(&self, query: &str) -> Option<String> {
+        let mut corrected = false;
+
+        let words: Vec<String> = query
+            .split_whitespace()
+            .map(|word| {
+                let tokens = self.tokenize(word);
+                if tokens
+                    .iter()
+                    .all(|token| self.index.contains_key(token.as_str()))
+                {
+                    return word.to_string();
+                }
+
+                let replacement = tokens.iter().find_map(|token| {
+                    Self::fuzzy_candidates(&self.index, token, DID_YOU_MEAN_DISTANCE)
+                        .into_iter()
+                        .min_by(|a, b| {
+                            a.0.cmp(&b.0)
+                                .then(
+                                    self.index[b.1]
+                                        .cardinality()
+                                        .cmp(&self.index[a.1].cardinality()),
+                                )
+                                .then(a.1.cmp(b.1))
+                        })
+                });
+
+                match replacement {
+                    Some((_, term)) => {
+                        corrected = true;
+                        term.to_string()
+                    }
+                    None => word.to_string(),
+                }
+            })
+            .collect();
+
+        corrected.then(|| words.join(" "))
+    }
+
+    pub const fn set_bm25(&mut self, bm25: Bm25) {
+        self.bm25 = bm25;
+    }
+
+    pub const fn set_field_boosts(&mut self, field_boosts: FieldBoosts) {
+        self.field_boosts = field_boosts;
+    }
+
+    pub fn set_default_operator(&mut self, operator: DefaultOperator) {
+        self.default_operator = operator;
+        self.invalidate_cache();
+    }
+
+    /// See `IndexBuilder::skip_missing`.
+    pub fn set_skip_missing(&mut self, skip_missing: bool) {
+        self.skip_missing = skip_missing;
+        self.invalidate_cache();
+    }
+
+    /// Scores matches with BM25. Only text lengths are tracked, so title
+    /// matches are length-normalized by the document's text length too. When
+    /// no document has any text tokens the average length is taken as 1.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn search_ranked(&self, query: &str) -> Vec<(u32, f64)> {
+        let Bm25 { k1, b } = self.bm25;
+        let average_length = if self.total_length == 0 {
+            1.0
+        } else {
+            self.total_length as f64 / self.lengths.len() as f64
+        };
+
+        self.rank(query, |tf, idf, doc_id| {
+            let length = f64::from(self.lengths[&doc_id]);
+            let norm = k1 * (1.0 - b + b * length / average_length);
+            idf.bm25 * tf * (k1 + 1.0) / (tf + norm)
+        })
+    }
+
+    /// Like `search_ranked`, keeping only results scoring at least
+    /// `min_score`. Scores are unnormalized BM25 sums, boosted per field, so
+    /// a useful threshold depends on the corpus and the query length.
+    #[must_use]
+    pub fn search_ranked_min(&self, query: &str, min_score: f64) -> Vec<(u32, f64)> {
+        let mut results = self.search_ranked(query);
+        results.retain(|&(_, score)| score >= min_score);
+        results
+    }
+
+    #[must_use]
+    pub fn search_tfidf(&self, query: &str) -> Vec<(u32, f64)> {
+        self.rank(query, |tf, idf, _| tf * idf.plain)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn rank<F>(&self, query: &str, score: F) -> Vec<(u32, f64)>
+    where
+        F: Fn(f64, Idf, u32) -> f64,
+    {
+        let mut tokens = self.tokenize(query);
+        tokens.sort_unstable();
+        tokens.dedup();
+
+        let total = self.lengths.len() as f64;
+        let mut scores: HashMap<u32, f64> = HashMap::new();
+
+        for token in &tokens {
+            if let Some(postings) = self.positions.get(token.as_str()) {
+                let idf = Idf::new(postings.len() as f64, total);
+                for (doc_id, positions) in postings {
+                    *scores.entry(*doc_id).or_insert(0.0) +=
+                        self.field_boosts.text * score(positions.len() as f64, idf, *doc_id);
+                }
+            }
+
+            if let Some(docs) = self.title_index.get(token.as_str()) {
+                let idf = Idf::new(docs.cardinality() as f64, total);
+                for doc_id in docs.iter() {
+                    *scores.entry(doc_id).or_insert(0.0) +=
+                        self.field_boosts.title * score(1.0, idf, doc_id);
+                }
+            }
+        }
+
+        let mut results: Vec<(u32, f64)> = scores.into_iter().collect();
+        results.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        results
+    }
+
+    /// Run-length encodes every bitmap where that is smaller. Loaders call
+    /// this once indexing is done; results are unchanged.
+    pub fn optimize(&mut self) {
+        let before = self.bitmap_bytes();
+
+        for index in [
+            &mut self.index,
+            &mut self.title_index,
+            &mut self.ngram_index,
+        ] {
+            for docs in index.values_mut() {
+                docs.run_optimize();
+            }
+        }
+
+        self.unoptimized_bitmap_bytes = Some(before);
+    }
+
+    /// Approximate memory used by the index: the serialized size of every
+    /// bitmap plus the bytes of stored documents, token strings, positions and
+    /// lengths. Allocator and hash table overhead is not counted.
+    #[must_use]
+    pub fn memory_bytes(&self) -> usize {
+        let tokens = self.tokens.bytes();
+        let documents: usize = self
+            .documents
+            .values()
+            .map(|doc| doc.title.len() + doc.url.len() + doc.text.len())
+            .sum();
+        let positions: usize = self
+            .positions
+            .values()
+            .flat_map(HashMap::values)
+            .map(|positions| (positions.len() + 1) * mem::size_of::<u32>())
+            .sum();
+        let lengths = self.lengths.len() * 2 * mem::size_of::<u32>();
+
+        self.bitmap_bytes() + tokens + documents + positions + lengths
+    }
+
+    fn bitmap_bytes(&self) -> usize {
+        [&self.index, &self.title_index, &self.ngram_index]
+            .iter()
+            .flat_map(|index| index.values())
+            .map(Bitmap::get_serialized_size_in_bytes)
+            .sum()
+    }
+
+    #[must_use]
+    pub fn get_document(&self, id: u32) -> Option<&StoredDocument> {
+        self.documents.get(&id)
+    }
+
+    /// Number of text tokens indexed for document `id`, after stop words are
+    /// dropped. `stats().average_document_length` is the mean over all of them.
+    #[must_use]
+    pub fn document_length(&self, id: u32) -> Option<u32> {
+        self.lengths.get(&id).copied()
+    }
+
+    /// Yields every stored document in ascending id order.
+    pub fn documents(&self) -> impl Iterator<Item = (u32, &StoredDocument)> + '_ {
+        let mut ids: Vec<u32> = self.documents.keys().copied().collect();
+        ids.sort_unstable();
+
+        ids.into_iter().map(move |id| (id, &self.documents[&id]))
+    }
+
+    /// Number of documents containing `term` once it has been tokenized like
+    /// indexed text.
+    #[must_use]
+    pub fn document_frequency(&self, term: &str) -> u64 {
+        self.tokenize(term)
+            .first()
+            .and_then(|token| self.index.get(token.as_str()))
+            .map_or(0, Bitmap::cardinality)
+    }
+
+    /// A copy of the documents containing `term`, tokenized like indexed text,
+    /// for callers combining bitmaps themselves.
+    #[must_use]
+    pub fn postings(&self, term: &str) -> Option<Bitmap> {
+        self.tokenize(term)
+            .first()
+            .and_then(|token| self.index.get(token.as_str()))
+            .cloned()
+    }
+
+    /// Describes each blank line and unreadable file or directory skipped
+    /// while loading, in the order they were met. These are not saved with
+    /// the index.
+    #[must_use]
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn stats(&self) -> IndexStats {
+        let average_document_length = if self.lengths.is_empty() {
+            0.0
+        } else {
+            self.total_length as f64 / self.lengths.len() as f64
+        };
+
+        IndexStats {
+            documents: self.documents.len(),
+            tokens: self.index.len(),
+            postings: self.index.values().map(Bitmap::cardinality).sum(),
+            average_document_length,
+            duplicates_skipped: self.duplicates_skipped,
+            malformed_skipped: self.malformed_skipped,
+            blank_lines_skipped: self.blank_lines_skipped,
+            files_skipped: self.files_skipped,
+            bitmap_bytes: self.bitmap_bytes(),
+            unoptimized_bitmap_bytes: self.unoptimized_bitmap_bytes,
+            memory_bytes: self.memory_bytes(),
+        }
+    }
+
+    /// Returns the stored text around the first word matching a query term,
+    /// keeping `radius` characters on each side.
+    #[must_use]
+    pub fn snippet(&self, doc_id: u32, query: &str, radius: usize) -> Option<String> {
+        let text = &self.documents.get(&doc_id)?.text;
+        let terms: HashSet<String> = self.tokenize(query).into_iter().collect();
+
+        let (start, word) = word_spans(text).find(|(_, word)| {
+            self.tokenize(word)
+                .iter()
+                .any(|token| terms.contains(token))
+        })?;
+        let end = start + word.len();
+
+        let from = text[..start]
+            .char_indices()
+            .rev()
+            .take(radius)
+            .last()
+            .map_or(start, |(i, _)| i);
+        let to = end
+            + text[end..]
+                .chars()
+                .take(radius)
+                .map(char::len_utf8)
+                .sum::<usize>();
+
+        let mut snippet = String::new();
+        if from > 0 {
+            snippet.push_str("...");
+        }
+        snippet.push_str(text[from..to].trim());
+        if to < text.len() {
+            snippet.push_str("...");
+        }
+
+        Some(snippet)
+    }
+
+    /// Returns the stored text of `doc_id` with every word matching a query
+    /// term wrapped in `pre` and `post`. Words are matched on their tokens,
+    /// so any form with the same stem is marked; surrounding punctuation is
+    /// left outside the markup.
+    #[must_use]
+    pub fn highlight(&self, doc_id: u32, query: &str, pre: &str, post: &str) -> Option<String> {
+        let text = &self.documents.get(&doc_id)?.text;
+        let terms: HashSet<String> = self.tokenize(query).into_iter().collect();
+
+        let mut highlighted = String::with_capacity(text.len());
+        let mut copied = 0;
+        for (start, word) in word_spans(text) {
+            if !self
+                .tokenize(word)
+                .iter()
+                .any(|token| terms.contains(token))
+            {
+                continue;
+            }
+            let Some(first) = word.find(char::is_alphanumeric) else {
+                continue;
+            };
+            let last = word
+                .char_indices()
+                .rev()
+                .find(|(_, c)| c.is_alphanumeric())
+                .map_or(word.len(), |(i, c)| i + c.len_utf8());
+
+            highlighted.push_str(&text[copied..start + first]);
+            highlighted.push_str(pre);
+            highlighted.push_str(&word[first..last]);
+            highlighted.push_str(post);
+            copied = start + last;
+        }
+        highlighted.push_str(&text[copied..]);
+
+        Some(highlighted)
+    }
+
+    pub fn add(&mut self, doc: &Document) {
+        let tokens = self.tokenize_document(doc);
+        self.insert(doc, tokens);
+    }
+
+    /// Adds `doc` under the caller's `id` rather than `doc.id`. Ids must be
+    /// unique, so an id that is already indexed is an error; use `update` to
+    /// replace an existing document.
+    pub fn add_with_id(&mut self, id: u32, doc: &Document) -> Result<(), CercamiError> {
+        if self.documents.contains_key(&id) {
+            return Err(CercamiError::InvalidInput(format!(
+                "document id {id} is already indexed"
+            )));
+        }
+
+        self.add(&Document { id, ..doc.clone() });
+        Ok(())
+    }
+
+    /// Adds every document in `docs`, with the same result as calling `add`
+    /// on each in order. Documents are tokenized in parallel when the
+    /// `parallel` feature is enabled, and postings are grouped per token so
+    /// each bitmap is updated once.
+    pub fn add_all(&mut self, docs: &[Document]) {
+        #[cfg(feature = "parallel")]
+        let tokenized: Vec<DocumentTokens> = docs
+            .par_iter()
+            .map(|doc| self.tokenize_document(doc))
+            .collect();
+        #[cfg(not(feature = "parallel"))]
+        let tokenized: Vec<DocumentTokens> =
+            docs.iter().map(|doc| self.tokenize_document(doc)).collect();
+
+        let mut postings: HashMap<String, Vec<u32>> = HashMap::new();
+        let mut title_postings: HashMap<String, Vec<u32>> = HashMap::new();
+        let mut ngram_postings: HashMap<String, Vec<u32>> = HashMap::new();
+
+        for (doc, tokens) in docs.iter().zip(tokenized) {
+            if self.is_duplicate(doc) {
+                self.duplicates_skipped += 1;
+                continue;
+            }
+
+            self.store(doc, &tokens.text);
+
+            if let Some(size) = self.ngram_size {
+                for gram in tokens.text.iter().flat_map(|token| ngrams(token, size)) {
+                    ngram_postings.entry(gram).or_default().push(doc.id);
+                }
+            }
+            for token in tokens.text {
+                postings.entry(token).or_default().push(doc.id);
+            }
+            for token in tokens.title {
+                title_postings.entry(token).or_default().push(doc.id);
+            }
+        }
+
+        for (index, postings) in [
+            (&mut self.index, postings),
+            (&mut self.title_index, title_postings),
+            (&mut self.ngram_index, ngram_postings),
+        ] {
+            for (token, ids) in postings {
+                index
+                    .entry(self.tokens.intern(&token))
+                    .or_insert_with(Bitmap::create)
+                    .add_many(&ids);
+            }
+        }
+    }
+
+    fn tokenize_document(&self, doc: &Document) -> DocumentTokens {
+        DocumentTokens {
+            text: self.tokenize(&doc.text),
+            title: self.tokenize(&doc.title),
+        }
+    }
+
+    fn insert(&mut self, doc: &Document, tokens: DocumentTokens) {
+        if self.is_duplicate(doc) {
+            self.duplicates_skipped += 1;
+            return;
+        }
+
+        self.insert_tokens(doc, tokens);
+    }
+
+    fn insert_tokens(&mut self, doc: &Document, tokens: DocumentTokens) {
+        self.store(doc, &tokens.text);
+        let DocumentTokens {
+            text: tokens,
+            title: title_tokens,
+        } = tokens;
+
+        if let Some(size) = self.ngram_size {
+            for gram in tokens.iter().flat_map(|token| ngrams(token, size)) {
+                self.ngram_index
+                    .entry(self.tokens.intern(&gram))
+                    .or_insert_with(Bitmap::create)
+                    .add(doc.id);
+            }
+        }
+
+        for token in tokens {
+            self.index
+                .entry(self.tokens.intern(&token))
+                .or_insert_with(Bitmap::create)
+                .add(doc.id);
+        }
+
+        for token in title_tokens {
+            self.title_index
+                .entry(self.tokens.intern(&token))
+                .or_insert_with(Bitmap::create)
+                .add(doc.id);
+        }
+    }
+
+    /// Records the stored copy, length and token positions of `doc`.
+    fn store(&mut self, doc: &Document, tokens: &[String]) {
+        self.invalidate_cache();
+        self.documents.insert(
+            doc.id,
+            StoredDocument {
+                title: doc.title.clone(),
+                url: doc.url.clone(),
+                text: doc.text.clone(),
+            },
+        );
+
+        let length = u32::try_from(tokens.len()).unwrap_or(u32::MAX);
+        if let Some(previous) = self.lengths.insert(doc.id, length) {
+            self.total_length -= u64::from(previous);
+        }
+        self.total_length += u64::from(length);
+
+        for (position, token) in (0..).zip(tokens) {
+            self.positions
+                .entry(self.tokens.intern(token))
+                .or_default()
+                .entry(doc.id)
+                .or_default()
+                .push(position);
+        }
+    }
+
+    /// Moves every document of `other` into this index. When this index is
+    /// not empty, `other`'s ids are offset past the largest id already here
+    /// so they never collide; the offset applied is returned. Both indexes
+    /// must use the same tokenizer settings and n-gram size.
+    pub fn merge(&mut self, other: Self) -> Result<u32, CercamiError> {
+        let settings = self.tokenizer.settings();
+        if settings.is_none() || settings != other.tokenizer.settings() {
+            return Err(CercamiError::Config(
+                "cannot merge indexes with different tokenizer settings".to_string(),
+            ));
+        }
+        if self.ngram_size != other.ngram_size {
+            return Err(CercamiError::Config(
+                "cannot merge indexes with different n-gram sizes".to_string(),
+            ));
+        }
+
+        let offset = self
+            .documents
+            .keys()
+            .max()
+            .map_or(Some(0), |max| max.checked_add(1));
+        let offset = offset
+            .filter(|offset| {
+                other
+                    .documents
+                    .keys()
+                    .max()
+                    .is_none_or(|max| max.checked_add(*offset).is_some())
+            })
+            .ok_or_else(|| CercamiError::InvalidInput("too many documents".to_string()))?;
+        self.invalidate_cache();
+
+        for (id, doc) in other.documents {
+            let id = id + offset;
+            if self.dedup {
+                self.content_hashes
+                    .entry(content_hash(&doc.text))
+                    .or_default()
+                    .push(id);
+            }
+            self.documents.insert(id, doc);
+        }
+        for (id, length) in other.lengths {
+            self.lengths.insert(id + offset, length);
+        }
+        self.total_length += other.total_length;
+
+        for (token, docs) in other.positions {
+            let postings = self
+                .positions
+                .entry(self.tokens.intern(&token))
+                .or_default();
+            for (id, positions) in docs {
+                postings.insert(id + offset, positions);
+            }
+        }
+
+        for (index, other_index) in [
+            (&mut self.index, other.index),
+            (&mut self.title_index, other.title_index),
+            (&mut self.ngram_index, other.ngram_index),
+        ] {
+            for (token, docs) in other_index {
+                let ids: Vec<u32> = docs.iter().map(|id| id + offset).collect();
+                index
+                    .entry(self.tokens.intern(&token))
+                    .or_insert_with(Bitmap::create)
+                    .add_many(&ids);
+            }
+        }
+
+        for (term, synonyms) in other.synonyms {
+            for synonym in synonyms {
+                self.link_synonyms(&term, &synonym);
+            }
+        }
+        self.duplicates_skipped += other.duplicates_skipped;
+        self.malformed_skipped += other.malformed_skipped;
+        self.blank_lines_skipped += other.blank_lines_skipped;
+        self.files_skipped += other.files_skipped;
+        self.skipped.extend(other.skipped);
+
+        Ok(offset)
+    }
+
+    /// Replaces the document indexed under `doc.id` with `doc`, or adds it.
+    /// With dedup enabled the new version is kept even if another document
+    /// has the same text, since the caller asked for this id explicitly.
+    pub fn update(&mut self, doc: &Document) {
+        self.remove(doc.id);
+        self.record_hash(doc);
+        let tokens = self.tokenize_document(doc);
+        self.insert_tokens(doc, tokens);
+    }
+
+    /// With dedup enabled, reports whether another document with the same
+    /// text is already indexed, and records the text of `doc` if not.
+    fn is_duplicate(&mut self, doc: &Document) -> bool {
+        if !self.dedup {
+            return false;
+        }
+
+        let documents = &self.documents;
+        let duplicate = self
+            .content_hashes
+            .get(&content_hash(&doc.text))
+            .into_iter()
+            .flatten()
+            .filter(|&&id| id != doc.id)
+            .any(|id| {
+                documents
+                    .get(id)
+                    .is_some_and(|existing| existing.text == doc.text)
+            });
+        if !duplicate {
+            self.record_hash(doc);
+        }
+        duplicate
+    }
+
+    fn record_hash(&mut self, doc: &Document) {
+        if !self.dedup {
+            return;
+        }
+
+        let ids = self
+            .content_hashes
+            .entry(content_hash(&doc.text))
+            .or_default();
+        if !ids.contains(&doc.id) {
+            ids.push(doc.id);
+        }
+    }
+
+    /// Removes every document whose url is exactly `url` and returns how many
+    /// were removed. Urls are compared as stored, without normalizing a
+    /// trailing slash, so `https://a.org/x` and `https://a.org/x/` differ.
+    pub fn remove_by_url(&mut self, url: &str) -> usize {
+        let doc_ids: Vec<u32> = self
+            .documents
+            .iter()
+            .filter(|(_, doc)| doc.url == url)
+            .map(|(&doc_id, _)| doc_id)
+            .collect();
+
+        for &doc_id in &doc_ids {
+            self.remove(doc_id);
+        }
+        doc_ids.len()
+    }
+
+    /// Removes the document with `doc_id`, if indexed. Its stored text and
+    /// title are tokenized again, so only the postings of its own tokens are
+    /// touched.
+    pub fn remove(&mut self, doc_id: u32) {
+        let Some(doc) = self.documents.remove(&doc_id) else {
+            return;
+        };
+        self.invalidate_cache();
+
+        let hash = content_hash(&doc.text);
+        if let Some(ids) = self.content_hashes.get_mut(&hash) {
+            ids.retain(|&id| id != doc_id);
+            if ids.is_empty() {
+                self.content_hashes.remove(&hash);
+            }
+        }
+
+        if let Some(length) = self.lengths.remove(&doc_id) {
+            self.total_length -= u64::from(length);
+        }
+
+        let mut text = self.tokenize(&doc.text);
+        let mut title = self.tokenize(&doc.title);
+        let mut grams: Vec<String> = self.ngram_size.map_or_else(Vec::new, |size| {
+            text.iter().flat_map(|token| ngrams(token, size)).collect()
+        });
+        for tokens in [&mut text, &mut title, &mut grams] {
+            tokens.sort_unstable();
+            tokens.dedup();
+        }
+
+        for (index, tokens) in [
+            (&mut self.index, &text),
+            (&mut self.title_index, &title),
+            (&mut self.ngram_index, &grams),
+        ] {
+            for token in tokens {
+                if let Some(docs) = index.get_mut(token.as_str()) {
+                    docs.remove(doc_id);
+                    if docs.is_empty() {
+                        index.remove(token.as_str());
+                    }
+                }
+            }
+        }
+
+        for token in &text {
+            if let Some(docs) = self.positions.get_mut(token.as_str()) {
+                docs.remove(&doc_id);
+                if docs.is_empty() {
+                    self.positions.remove(token.as_str());
+                }
+            }
+        }
+
+        for token in text.iter().chain(&title).chain(&grams) {
+            self.tokens.release(token);
+        }
+    }
+
+    /// Removes every document, keeping the tokenizer, synonyms and other
+    /// settings so the index can be refilled.
+    pub fn clear(&mut self) {
+        self.index.clear();
+        self.title_index.clear();
+        self.ngram_index.clear();
+        self.positions.clear();
+        self.tokens.clear();
+        self.lengths.clear();
+        self.total_length = 0;
+        self.documents.clear();
+        self.content_hashes.clear();
+        self.duplicates_skipped = 0;
+        self.malformed_skipped = 0;
+        self.blank_lines_skipped = 0;
+        self.files_skipped = 0;
+        self.skipped.clear();
+        self.unoptimized_bitmap_bytes = None;
+        self.invalidate_cache();
+    }
+
+    /// Tokenizes `text` like indexed text, pairing each token with the
+    /// position it would be stored at. Positions count surviving tokens only,
+    /// so stop words do not leave gaps.
+    #[must_use]
+    pub fn tokenize_positions(&self, text: &str) -> Vec<(String, usize)> {
+        self.tokenize(text)
+            .into_iter()
+            .enumerate()
+            .map(|(position, token)| (token, position))
+            .collect()
+    }
+
+    /// Splits `text` into the tokens indexing stores for it: normalized,
+    /// stop words removed and stemmed.
+    #[must_use]
+    pub fn tokenize(&self, text: &str) -> Vec<String> {
+        self.tokenizer.tokenize(text)
+    }
+
+    /// The form `word` is indexed under, or an empty string when indexing
+    /// would drop it, e.g. as a stop word. Only the first token is kept if
+    /// `word` splits into several.
+    #[must_use]
+    pub fn stem(&self, word: &str) -> String {
+        self.tokenize(word).into_iter().next().unwrap_or_default()
+    }
+}
+
+fn lock(cache: &Mutex<QueryCache>) -> MutexGuard<'_, QueryCache> {
+    cache.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn url_host(url: &str) -> Option<String> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?.trim_end_matches('.');
+
+    (!host.is_empty()).then(|| host.to_lowercase())
+}
+
+fn content_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn word_spans(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut offset = 0;
+
+    text.split_inclusive(char::is_whitespace)
+        .filter_map(move |piece| {
+            let start = offset;
+            offset += piece.len();
+            let word = piece.trim_end();
+            (!word.is_empty()).then_some((start, word))
+        })
+}
+
+fn ngrams(token: &str, size: usize) -> Vec<String> {
+    let chars: Vec<char> = token.chars().collect();
+
+    if chars.len() <= size {
+        return vec![token.to_string()];
+    }
+
+    chars
+        .windows(size)
+        .map(|gram| gram.iter().collect())
+        .collect()
+}
+
+fn levenshtein(a: &str, b: &str, max_distance: usize) -> Option<usize> {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        if current.iter().all(|&distance| distance > max_distance) {
+            return None;
+        }
+        previous = current;
+    }
+
+    Some(previous[b.len()]).filter(|&distance| distance <= max_distance)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredDocument {
+    pub title: String,
+    pub url: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+struct DocumentTokens {
+    text: Vec<String>,
+    title: Vec<String>,
+}
+
+pub struct SearchResult {
+    pub id: u32,
+    pub title: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexStats {
+    pub documents: usize,
+    pub tokens: usize,
+    pub postings: u64,
+    pub average_document_length: f64,
+    pub duplicates_skipped: u64,
+    /// Malformed XML documents skipped because the index is not strict.
+    pub malformed_skipped: u64,
+    /// Blank lines skipped in NDJSON input.
+    pub blank_lines_skipped: u64,
+    /// Files and subdirectories skipped because they could not be read.
+    pub files_skipped: u64,
+    pub bitmap_bytes: usize,
+    /// Bitmap size measured by the last `optimize`, before it ran.
+    pub unoptimized_bitmap_bytes: Option<usize>,
+    /// Approximate total, see `Index::memory_bytes`.
+    pub memory_bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub ids: Vec<u32>,
+    pub total: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Document {
+    pub title: String,
+    pub url: String,
+    #[serde(rename = "abstract")]
+    pub text: String,
+    #[serde(skip)]
+    pub id: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: u32, title: &str, text: &str) -> Document {
+        Document {
+            id,
+            title: title.to_string(),
+            url: format!("https://example.com/{id}"),
+            text: text.to_string(),
+        }
+    }
+
+    fn languages() -> Index {
+        let mut index = Index::default();
+        index.add(&document(0, "Rust", "rust is a systems language"));
+        index.add(&document(1, "Python", "python and rust bindings"));
+        index.add(&document(2, "Go", "go is a language with goroutines"));
+        index
+    }
+
+    #[test]
+    fn fuzzy_search_subtracts_exclusions() {
+        let index = languages();
+        for distance in 0..=2 {
+            assert_eq!(
+                index.search_fuzzy("rust -python", distance).to_vec(),
+                vec![0]
+            );
+            assert_eq!(index.search_fuzzy("rusty -pyton", distance).to_vec(), {
+                if distance == 0 {
+                    Vec::<u32>::new()
+                } else {
+                    vec![0, 1]
+                }
+            });
+            assert!(index.search_fuzzy("-python", distance).is_empty());
+        }
+    }
+
+    #[test]
+    fn fuzzy_search_without_terms_matches_everything() {
+        let index = languages();
+        for distance in 0..=2 {
+            assert_eq!(index.search_fuzzy("", distance).to_vec(), vec![0, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn ranked_search_scores_title_only_corpora() {
+        let mut index = Index::default();
+        index.add(&document(0, "Rust", ""));
+        index.add(&document(1, "Go", "the and of"));
+
+        let results = index.search_ranked("rust");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 0);
+        assert!(results[0].1.is_finite() && results[0].1 > 0.0);
+        assert_eq!(index.search_ranked_min("rust", 0.0), results);
+    }
+
+    #[test]
+    fn updating_to_duplicate_text_keeps_the_document() {
+        let mut index = Index::builder().dedup(true).build().unwrap();
+        index.add(&document(0, "A", "ferris the crab"));
+        index.add(&document(1, "B", "a lobster"));
+
+        index.update(&document(1, "B", "ferris the crab"));
+        assert!(index.get_document(1).is_some());
+        assert_eq!(index.search("ferris").to_vec(), vec![0, 1]);
+        assert!(index.search("lobster").is_empty());
+
+        index.remove(0);
+        index.add(&document(2, "C", "ferris the crab"));
+        assert!(index.get_document(2).is_none());
+        assert_eq!(index.stats().duplicates_skipped, 1);
+
+        index.remove(1);
+        index.add(&document(3, "D", "ferris the crab"));
+        assert_eq!(index.search("ferris").to_vec(), vec![3]);
+    }
+
+    #[test]
+    fn remove_drops_only_the_documents_own_postings() {
+        let docs = [
+            document(0, "Rust", "rust is a systems language"),
+            document(1, "Python", "python and rust bindings"),
+            document(2, "Go", "go is a language with goroutines"),
+        ];
+        let mut index = Index::builder().ngrams(3).build().unwrap();
+        let mut expected = Index::builder().ngrams(3).build().unwrap();
+        for doc in &docs {
+            index.add(doc);
+            if doc.id != 1 {
+                expected.add(doc);
+            }
+        }
+
+        index.remove(1);
+        index.remove(1);
+        assert_eq!(index.stats(), expected.stats());
+        assert_eq!(index.tokens.bytes(), expected.tokens.bytes());
+        assert_eq!(index.ngram_index.len(), expected.ngram_index.len());
+        assert_eq!(index.positions.len(), expected.positions.len());
+        assert_eq!(index.search("rust").to_vec(), vec![0]);
+        assert!(index.search("title:python").is_empty());
+    }
+
+    #[test]
+    fn count_matches_search_cardinality() {
+        let mut index = languages();
+        index.add(&document(3, "Ferris", "the rust crab"));
+        index.add_synonyms("crab", vec!["crustacean".to_string()]);
+        for query in [
+            "",
+            "rust",
+            "rust language",
+            "rust -python",
+            "-python",
+            "title:rust",
+            "crustacean",
+            "rust crustacean",
+            "go language -rust",
+            "zebra",
+            "the",
+        ] {
+            assert_eq!(
+                index.count(query),
+                index.search(query).cardinality(),
+                "{query}"
+            );
+        }
+    }
+
+    #[test]
+    fn prefixes_are_normalized_like_indexed_words() {
+        let mut index = Index::builder()
+            .fold_diacritics(true)
+            .stemming(false)
+            .build()
+            .unwrap();
+        index.add(&document(0, "Cafés", "un café crème"));
+        index.add(&document(1, "Tea", "a cup of tea"));
+
+        for prefix in ["caf", "CAFÉ", "cafe\u{301}", "crè*"] {
+            assert_eq!(index.search_prefix(prefix).to_vec(), vec![0], "{prefix}");
+        }
+        assert_eq!(index.suggest("Cré", 5), vec!["creme"]);
+
+        let mut plain = Index::builder().stemming(false).build().unwrap();
+        plain.add(&document(0, "Café", "un café"));
+        assert_eq!(plain.search_prefix("cafe\u{301}").to_vec(), vec![0]);
+    }
+
+    #[test]
+    fn phrases_match_adjacent_tokens_in_order() {
+        let mut index = languages();
+        index.add(&document(3, "Mixed", "language systems rust"));
+
+        assert_eq!(index.search_phrase("systems language").to_vec(), vec![0]);
+        assert_eq!(index.search_phrase("rust is a systems").to_vec(), vec![0]);
+        assert_eq!(index.search_phrase("rust bindings").to_vec(), vec![1]);
+        assert!(index
+            .search_phrase("language systems rust bindings")
+            .is_empty());
+        assert_eq!(index.search_phrase("rust").to_vec(), vec![0, 1, 3]);
+        assert!(index.search_phrase("the").is_empty());
+    }
+
+    #[test]
+    fn near_matches_within_the_distance_in_either_order() {
+        let mut index = Index::default();
+        index.add(&document(0, "A", "ferris crab lobster"));
+        index.add(&document(1, "B", "lobster shell ocean ferris"));
+
+        assert_eq!(index.search_near("ferris", "lobster", 2).to_vec(), vec![0]);
+        assert_eq!(
+            index.search_near("lobster", "ferris", 3).to_vec(),
+            vec![0, 1]
+        );
+        assert!(index.search_near("ferris", "lobster", 1).is_empty());
+        assert!(index.search_near("ferris", "zebra", 5).is_empty());
+        assert_eq!(
+            index
+                .search_boolean("ferris NEAR/1 crab OR ocean")
+                .unwrap()
+                .to_vec(),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn merge_offsets_ids_and_keeps_both_corpora_searchable() {
+        let mut index = languages();
+        let mut other = Index::default();
+        other.add(&document(0, "Ferris", "the rust crab"));
+        other.add(&document(1, "Zig", "zig language"));
+
+        assert_eq!(index.merge(other).unwrap(), 3);
+        assert_eq!(index.search("rust").to_vec(), vec![0, 1, 3]);
+        assert_eq!(index.search("language").to_vec(), vec![0, 2, 4]);
+        assert_eq!(index.get_document(4).unwrap().title, "Zig");
+        assert_eq!(index.search_phrase("rust crab").to_vec(), vec![3]);
+
+        let stemless = Index::builder().stemming(false).build().unwrap();
+        assert!(matches!(
+            index.merge(stemless),
+            Err(CercamiError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn update_replaces_the_old_tokens() {
+        let mut index = languages();
+        index.update(&document(2, "Go", "gophers everywhere"));
+
+        assert!(index.search("goroutines").is_empty());
+        assert_eq!(index.search("gophers").to_vec(), vec![2]);
+        assert_eq!(index.search("title:go").to_vec(), vec![2]);
+        assert_eq!(index.stats().documents, 3);
+    }
+
+    #[test]
+    fn mutations_clear_the_query_cache() {
+        let mut index = languages();
+        index.set_query_cache(8);
+        assert_eq!(index.search("rust").to_vec(), vec![0, 1]);
+
+        index.add(&document(3, "Ferris", "the rust crab"));
+        assert_eq!(index.search("rust").to_vec(), vec![0, 1, 3]);
+        index.remove(0);
+        assert_eq!(index.search("rust").to_vec(), vec![1, 3]);
+        index.update(&document(1, "Python", "python only"));
+        assert_eq!(index.search("rust").to_vec(), vec![3]);
+        index.set_default_operator(DefaultOperator::Or);
+        assert_eq!(index.search("rust python").to_vec(), vec![1, 3]);
+        index.add_synonyms("rust", vec!["python".to_string()]);
+        assert_eq!(index.search("rust").to_vec(), vec![1, 3]);
+        index.clear();
+        assert!(index.search("rust").is_empty());
+    }
+
+    fn config(args: &[&str]) -> Result<Config, CercamiError> {
+        Config::new(
+            std::iter::once("cercami")
+                .chain(args.iter().copied())
+                .map(str::to_string),
+        )
+    }
+
+    #[test]
+    fn parses_cli_options_and_positionals() {
+        let parsed = config(&["--limit", "3", "--json", "a.xml", "b.xml", "rust lang"]).unwrap();
+        assert_eq!(parsed.db_paths, vec!["a.xml", "b.xml"]);
+        assert_eq!(parsed.query, "rust lang");
+        assert_eq!(parsed.limit, 3);
+        assert!(parsed.json && !parsed.stream);
+
+        let parsed = config(&["a.xml", "--stream", "--limit=0", "rust"]).unwrap();
+        assert_eq!(parsed.limit, 0);
+        assert!(parsed.stream && !parsed.json);
+        assert_eq!(config(&["a.xml", "rust"]).unwrap().limit, DEFAULT_LIMIT);
+    }
+
+    #[test]
+    fn rejects_bad_cli_arguments() {
+        for args in [
+            &[][..],
+            &["a.xml"],
+            &["--limit"],
+            &["--limit", "ten", "a.xml", "rust"],
+            &["--verbose", "a.xml", "rust"],
+        ] {
+            assert!(
+                matches!(config(args), Err(CercamiError::Config(_))),
+                "{:?} was accepted",
+                args
+            );
+        }
+    }
 }
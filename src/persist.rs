@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Read, Write};
+
+use croaring::bitmap::Bitmap;
+use rustc_hash::FxHashMap;
+use serde::de::{self, Deserializer};
+use serde::ser::{self, Serializer};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    content_hash, Bm25, CercamiError, DefaultOperator, DefaultTokenizer, FieldBoosts, Index,
+    Interner, StoredDocument, Token, TokenizerSettings,
+};
+
+const MAGIC: &[u8; 4] = b"CRCM";
+const FORMAT_VERSION: u32 = 1;
+
+const BINCODE_MAGIC: &[u8; 4] = b"CRCB";
+const BINCODE_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Metadata<'a> {
+    documents: &'a FxHashMap<u32, StoredDocument>,
+    positions: &'a FxHashMap<Token, HashMap<u32, Vec<u32>>>,
+    lengths: &'a FxHashMap<u32, u32>,
+    synonyms: &'a HashMap<String, Vec<String>>,
+    dedup: bool,
+    duplicates_skipped: u64,
+    malformed_skipped: u64,
+    blank_lines_skipped: u64,
+    files_skipped: u64,
+    ngram_size: Option<usize>,
+    bm25: Bm25,
+    field_boosts: FieldBoosts,
+    default_operator: DefaultOperator,
+    skip_missing: bool,
+    tokenizer: Option<TokenizerSettings>,
+}
+
+#[derive(Deserialize)]
+struct StoredMetadata {
+    documents: FxHashMap<u32, StoredDocument>,
+    positions: HashMap<String, HashMap<u32, Vec<u32>>>,
+    lengths: FxHashMap<u32, u32>,
+    #[serde(default)]
+    synonyms: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    dedup: bool,
+    #[serde(default)]
+    duplicates_skipped: u64,
+    #[serde(default)]
+    malformed_skipped: u64,
+    #[serde(default)]
+    blank_lines_skipped: u64,
+    #[serde(default)]
+    files_skipped: u64,
+    ngram_size: Option<usize>,
+    bm25: Bm25,
+    #[serde(default)]
+    field_boosts: FieldBoosts,
+    #[serde(default)]
+    default_operator: DefaultOperator,
+    #[serde(default)]
+    skip_missing: bool,
+    tokenizer: Option<TokenizerSettings>,
+}
+
+/// `Index` as seen by serde: the metadata followed by the bitmaps as
+/// croaring's native serialization.
+#[derive(Serialize)]
+struct Snapshot<'a> {
+    metadata: Metadata<'a>,
+    index: Bitmaps<'a>,
+    title_index: Bitmaps<'a>,
+    ngram_index: Bitmaps<'a>,
+}
+
+#[derive(Deserialize)]
+struct StoredSnapshot {
+    metadata: StoredMetadata,
+    #[serde(deserialize_with = "deserialize_bitmaps")]
+    index: HashMap<String, Bitmap>,
+    #[serde(deserialize_with = "deserialize_bitmaps")]
+    title_index: HashMap<String, Bitmap>,
+    #[serde(deserialize_with = "deserialize_bitmaps")]
+    ngram_index: HashMap<String, Bitmap>,
+}
+
+/// Serializes the whole index, so it can be stored with any serde format.
+/// As with `save`, this fails for an index with a custom tokenizer or a
+/// normalizer.
+impl Serialize for Index {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Snapshot {
+            metadata: self.metadata().map_err(ser::Error::custom)?,
+            index: Bitmaps(&self.index),
+            title_index: Bitmaps(&self.title_index),
+            ngram_index: Bitmaps(&self.ngram_index),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Index {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = StoredSnapshot::deserialize(deserializer)?;
+        Self::restore(
+            snapshot.metadata,
+            snapshot.index,
+            snapshot.title_index,
+            snapshot.ngram_index,
+        )
+        .map_err(de::Error::custom)
+    }
+}
+
+impl Index {
+    /// Writes the index to `path`. Only the default tokenizer's settings can
+    /// be stored, so an index with a custom tokenizer or a normalizer cannot
+    /// be saved.
+    pub fn save(&self, path: &str) -> Result<(), CercamiError> {
+        let metadata = self.metadata()?;
+        let file = fs::File::create(path)?;
+        let mut writer = io::BufWriter::new(file);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+
+        write_bytes(&mut writer, &serde_json::to_vec(&metadata)?)?;
+
+        for index in [&self.index, &self.title_index, &self.ngram_index] {
+            write_bitmaps(&mut writer, index)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Loads an index written by `save`.
+    pub fn load(path: &str) -> Result<Self, CercamiError> {
+        let file = fs::File::open(path)?;
+        let mut reader = io::BufReader::new(file);
+
+        check_header(&mut reader, path, *MAGIC, FORMAT_VERSION)?;
+
+        let metadata: StoredMetadata = serde_json::from_slice(&read_bytes(&mut reader)?)?;
+        let index = read_bitmaps(&mut reader)?;
+        let title_index = read_bitmaps(&mut reader)?;
+        let ngram_index = read_bitmaps(&mut reader)?;
+
+        Self::restore(metadata, index, title_index, ngram_index)
+    }
+
+    /// Like `save`, but writes the whole index as one compact bincode
+    /// stream, with the bitmaps embedded in croaring's native format.
+    pub fn save_bincode(&self, path: &str) -> Result<(), CercamiError> {
+        self.metadata()?;
+        let file = fs::File::create(path)?;
+        let mut writer = io::BufWriter::new(file);
+
+        writer.write_all(BINCODE_MAGIC)?;
+        writer.write_all(&BINCODE_FORMAT_VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut writer, self)?;
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Loads an index written by `save_bincode`.
+    pub fn load_bincode(path: &str) -> Result<Self, CercamiError> {
+        let file = fs::File::open(path)?;
+        let mut reader = io::BufReader::new(file);
+
+        check_header(&mut reader, path, *BINCODE_MAGIC, BINCODE_FORMAT_VERSION)?;
+        Ok(bincode::deserialize_from(reader)?)
+    }
+
+    fn metadata(&self) -> Result<Metadata<'_>, CercamiError> {
+        let tokenizer = self.tokenizer.settings().ok_or_else(|| {
+            CercamiError::Config(
+                "an index with a custom tokenizer or a normalizer cannot be saved".to_string(),
+            )
+        })?;
+
+        Ok(Metadata {
+            documents: &self.documents,
+            positions: &self.positions,
+            lengths: &self.lengths,
+            synonyms: &self.synonyms,
+            dedup: self.dedup,
+            duplicates_skipped: self.duplicates_skipped,
+            malformed_skipped: self.malformed_skipped,
+            blank_lines_skipped: self.blank_lines_skipped,
+            files_skipped: self.files_skipped,
+            ngram_size: self.ngram_size,
+            bm25: self.bm25,
+            field_boosts: self.field_boosts,
+            default_operator: self.default_operator,
+            skip_missing: self.skip_missing,
+            tokenizer: Some(tokenizer),
+        })
+    }
+
+    fn restore(
+        metadata: StoredMetadata,
+        index: HashMap<String, Bitmap>,
+        title_index: HashMap<String, Bitmap>,
+        ngram_index: HashMap<String, Bitmap>,
+    ) -> Result<Self, CercamiError> {
+        let tokenizer = metadata
+            .tokenizer
+            .map(DefaultTokenizer::from)
+            .ok_or_else(|| {
+                CercamiError::UnsupportedInput(
+                    "index was saved without tokenizer settings and cannot be restored".to_string(),
+                )
+            })?;
+
+        // The maps are read separately, so their tokens are shared again here.
+        let mut tokens = Interner::default();
+        let mut intern = |index: HashMap<String, Bitmap>| -> FxHashMap<Token, Bitmap> {
+            index
+                .into_iter()
+                .map(|(token, docs)| (tokens.intern(&token), docs))
+                .collect()
+        };
+        let index = intern(index);
+        let title_index = intern(title_index);
+        let ngram_index = intern(ngram_index);
+        let positions = metadata
+            .positions
+            .into_iter()
+            .map(|(token, docs)| (tokens.intern(&token), docs))
+            .collect();
+
+        let mut content_hashes = HashMap::new();
+        if metadata.dedup {
+            for (&doc_id, doc) in &metadata.documents {
+                content_hashes
+                    .entry(content_hash(&doc.text))
+                    .or_insert_with(Vec::new)
+                    .push(doc_id);
+            }
+        }
+
+        Ok(Self {
+            index,
+            title_index,
+            ngram_index,
+            positions,
+            tokens,
+            total_length: metadata.lengths.values().copied().map(u64::from).sum(),
+            lengths: metadata.lengths,
+            documents: metadata.documents,
+            synonyms: metadata.synonyms,
+            dedup: metadata.dedup,
+            content_hashes,
+            duplicates_skipped: metadata.duplicates_skipped,
+            strict: true,
+            malformed_skipped: metadata.malformed_skipped,
+            blank_lines_skipped: metadata.blank_lines_skipped,
+            files_skipped: metadata.files_skipped,
+            skipped: Vec::new(),
+            unoptimized_bitmap_bytes: None,
+            tokenizer: Box::new(tokenizer),
+            ngram_size: metadata.ngram_size,
+            bm25: metadata.bm25,
+            field_boosts: metadata.field_boosts,
+            default_operator: metadata.default_operator,
+            skip_missing: metadata.skip_missing,
+            cache: None,
+        })
+    }
+}
+
+struct BitmapBytes<'a>(&'a Bitmap);
+
+impl Serialize for BitmapBytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0.serialize())
+    }
+}
+
+struct Bitmaps<'a>(&'a FxHashMap<Token, Bitmap>);
+
+impl Serialize for Bitmaps<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            self.0
+                .iter()
+                .map(|(token, docs)| (token, BitmapBytes(docs))),
+        )
+    }
+}
+
+fn deserialize_bitmaps<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, Bitmap>, D::Error> {
+    HashMap::<String, Vec<u8>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(token, bytes)| {
+            Bitmap::try_deserialize(&bytes)
+                .map(|docs| (token, docs))
+                .ok_or_else(|| de::Error::custom("corrupt bitmap"))
+        })
+        .collect()
+}
+
+fn check_header<R: Read>(
+    reader: &mut R,
+    path: &str,
+    expected_magic: [u8; 4],
+    expected_version: u32,
+) -> Result<(), CercamiError> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != expected_magic {
+        return Err(CercamiError::UnsupportedInput(format!(
+            "{path} is not a cercami index file"
+        )));
+    }
+
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != expected_version {
+        return Err(CercamiError::UnsupportedInput(format!(
+            "unsupported index format version {version}, expected {expected_version}"
+        )));
+    }
+
+    Ok(())
+}
+
+fn write_bitmaps<W: Write>(writer: &mut W, index: &FxHashMap<Token, Bitmap>) -> io::Result<()> {
+    writer.write_all(&(index.len() as u64).to_le_bytes())?;
+
+    for (token, docs) in index {
+        write_bytes(writer, token.as_bytes())?;
+        write_bytes(writer, &docs.serialize())?;
+    }
+
+    Ok(())
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_bitmaps<R: Read>(reader: &mut R) -> Result<HashMap<String, Bitmap>, CercamiError> {
+    let count = read_u64(reader)?;
+    let mut index = HashMap::new();
+
+    for _ in 0..count {
+        let token = String::from_utf8(read_bytes(reader)?).map_err(|_| corrupt("token"))?;
+        let docs =
+            Bitmap::try_deserialize(&read_bytes(reader)?).ok_or_else(|| corrupt("bitmap"))?;
+        index.insert(token, docs);
+    }
+
+    Ok(index)
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>, CercamiError> {
+    let length = usize::try_from(read_u64(reader)?).map_err(|_| corrupt("length"))?;
+    let mut bytes = Vec::new();
+    reader.take(length as u64).read_to_end(&mut bytes)?;
+
+    if bytes.len() != length {
+        return Err(CercamiError::InvalidInput(
+            "unexpected end of index file".to_string(),
+        ));
+    }
+
+    Ok(bytes)
+}
+
+fn corrupt(what: &str) -> CercamiError {
+    CercamiError::InvalidInput(format!("corrupt {what} in index file"))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::*;
+    use crate::Document;
+
+    fn sample() -> Index {
+        let mut index = Index::default();
+        for (id, (title, text)) in [("Crab", "ferris the crab"), ("Rust", "a language")]
+            .iter()
+            .enumerate()
+        {
+            index.add(&Document {
+                id: u32::try_from(id).unwrap(),
+                title: (*title).to_string(),
+                url: format!("https://example.com/{id}"),
+                text: (*text).to_string(),
+            });
+        }
+        index
+    }
+
+    fn assert_same(loaded: &Index, index: &Index) {
+        for query in ["crab", "rust", "language", "title:rust", "zebra"] {
+            assert_eq!(loaded.search(query).to_vec(), index.search(query).to_vec());
+        }
+        assert_eq!(loaded.stats(), index.stats());
+    }
+
+    /// A path under the temp directory, unique to this process, whose file
+    /// is removed on drop even if the test fails.
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            Self(env::temp_dir().join(format!("cercami-{}-{name}", process::id())))
+        }
+
+        fn as_str(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let index = sample();
+        let path = TempPath::new("round-trip.idx");
+
+        index.save(path.as_str()).unwrap();
+        assert_same(&Index::load(path.as_str()).unwrap(), &index);
+
+        index.save_bincode(path.as_str()).unwrap();
+        assert_same(&Index::load_bincode(path.as_str()).unwrap(), &index);
+    }
+
+    #[test]
+    fn rejects_other_files_and_versions() {
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        assert!(check_header(&mut header.as_slice(), "index", *MAGIC, FORMAT_VERSION).is_ok());
+
+        let mut newer = MAGIC.to_vec();
+        newer.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        let err = check_header(&mut newer.as_slice(), "index", *MAGIC, FORMAT_VERSION);
+        assert!(
+            matches!(err, Err(CercamiError::UnsupportedInput(message)) if message.contains("version"))
+        );
+
+        let err = check_header(
+            &mut header.as_slice(),
+            "index",
+            *BINCODE_MAGIC,
+            FORMAT_VERSION,
+        );
+        assert!(
+            matches!(err, Err(CercamiError::UnsupportedInput(message)) if message.contains("not a cercami index"))
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use std::error;
+use std::fmt;
+
+/// How deeply parentheses and `NOT`s may nest, so a hostile query cannot
+/// exhaust the stack of the recursive parser.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Term(String),
+    // Runs of the same operator are kept flat, so a long query does not
+    // build a deep tree.
+    And(Vec<Self>),
+    Or(Vec<Self>),
+    Not(Box<Self>),
+    Near(String, String, u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnbalancedParentheses,
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    TooDeep,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnbalancedParentheses => write!(f, "unbalanced parentheses in query"),
+            Self::UnexpectedToken(token) => write!(f, "expected a term but found `{token}`"),
+            Self::UnexpectedEnd => write!(f, "query ends with a dangling operator"),
+            Self::TooDeep => write!(f, "query nests more than {MAX_DEPTH} levels deep"),
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    And,
+    Or,
+    Not,
+    Near(u32),
+    Term(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LeftParen => write!(f, "("),
+            Self::RightParen => write!(f, ")"),
+            Self::And => write!(f, "AND"),
+            Self::Or => write!(f, "OR"),
+            Self::Not => write!(f, "NOT"),
+            Self::Near(distance) => write!(f, "NEAR/{distance}"),
+            Self::Term(term) => write!(f, "{term}"),
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser {
+        tokens: lex(input),
+        position: 0,
+        depth: 0,
+    };
+
+    let expr = parser.or()?;
+    match parser.next() {
+        None => Ok(expr),
+        Some(Token::RightParen) => Err(ParseError::UnbalancedParentheses),
+        Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+    }
+}
+
+fn lex(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+
+    for c in input.chars() {
+        match c {
+            '(' | ')' => {
+                flush(&mut word, &mut tokens);
+                tokens.push(if c == '(' {
+                    Token::LeftParen
+                } else {
+                    Token::RightParen
+                });
+            }
+            c if c.is_whitespace() => flush(&mut word, &mut tokens),
+            c => word.push(c),
+        }
+    }
+    flush(&mut word, &mut tokens);
+
+    tokens
+}
+
+fn flush(word: &mut String, tokens: &mut Vec<Token>) {
+    if word.is_empty() {
+        return;
+    }
+
+    tokens.push(match word.as_str() {
+        "AND" => Token::And,
+        "OR" => Token::Or,
+        "NOT" => Token::Not,
+        _ => word
+            .strip_prefix("NEAR/")
+            .and_then(|d| d.parse().ok())
+            .map_or_else(|| Token::Term(word.clone()), Token::Near),
+    });
+    word.clear();
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Expr, ParseError>,
+    ) -> Result<Expr, ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(ParseError::TooDeep);
+        }
+
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut operands = vec![self.and()?];
+
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            operands.push(self.and()?);
+        }
+
+        Ok(flatten(operands, Expr::Or))
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut operands = vec![self.not()?];
+
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                }
+                Some(Token::Term(_) | Token::LeftParen | Token::Not) => {}
+                _ => break,
+            }
+
+            operands.push(self.not()?);
+        }
+
+        Ok(flatten(operands, Expr::And))
+    }
+
+    fn not(&mut self) -> Result<Expr, ParseError> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.nested(Self::not)?)));
+        }
+
+        self.near()
+    }
+
+    fn near(&mut self) -> Result<Expr, ParseError> {
+        let left = self.primary()?;
+
+        let Some(&Token::Near(distance)) = self.peek() else {
+            return Ok(left);
+        };
+        let Expr::Term(a) = left else {
+            return Err(ParseError::UnexpectedToken(format!("NEAR/{distance}")));
+        };
+        self.next();
+
+        match self.next() {
+            Some(Token::Term(b)) => Ok(Expr::Near(a, b, distance)),
+            Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        match self.next() {
+            Some(Token::Term(term)) => Ok(Expr::Term(term)),
+            Some(Token::LeftParen) => {
+                let expr = self.nested(Self::or)?;
+                match self.next() {
+                    Some(Token::RightParen) => Ok(expr),
+                    _ => Err(ParseError::UnbalancedParentheses),
+                }
+            }
+            Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+}
+
+fn flatten(mut operands: Vec<Expr>, operator: fn(Vec<Expr>) -> Expr) -> Expr {
+    if operands.len() == 1 {
+        operands.remove(0)
+    } else {
+        operator(operands)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(term: &str) -> Expr {
+        Expr::Term(term.to_string())
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("a b OR c AND d").unwrap(),
+            Expr::Or(vec![
+                Expr::And(vec![term("a"), term("b")]),
+                Expr::And(vec![term("c"), term("d")]),
+            ])
+        );
+    }
+
+    #[test]
+    fn parentheses_and_not_group() {
+        assert_eq!(
+            parse("NOT (a OR b) c").unwrap(),
+            Expr::And(vec![
+                Expr::Not(Box::new(Expr::Or(vec![term("a"), term("b")]))),
+                term("c"),
+            ])
+        );
+        assert_eq!(
+            parse("a NEAR/3 b").unwrap(),
+            Expr::Near("a".to_string(), "b".to_string(), 3)
+        );
+    }
+
+    #[test]
+    fn long_runs_stay_flat() {
+        let query = vec!["a"; 10_000].join(" OR ");
+        assert!(matches!(parse(&query), Ok(Expr::Or(operands)) if operands.len() == 10_000));
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        assert_eq!(parse("(a OR b"), Err(ParseError::UnbalancedParentheses));
+        assert_eq!(parse("a OR b)"), Err(ParseError::UnbalancedParentheses));
+        assert_eq!(parse("a AND"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(
+            parse("OR a"),
+            Err(ParseError::UnexpectedToken("OR".to_string()))
+        );
+        assert_eq!(
+            parse("(a b) NEAR/2 c"),
+            Err(ParseError::UnexpectedToken("NEAR/2".to_string()))
+        );
+    }
+
+    #[test]
+    fn caps_nesting() {
+        let nested = |depth| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(parse(&nested(MAX_DEPTH + 1)), Err(ParseError::TooDeep));
+        assert_eq!(
+            parse(&format!("{}a", "NOT ".repeat(MAX_DEPTH + 1))),
+            Err(ParseError::TooDeep)
+        );
+    }
+}
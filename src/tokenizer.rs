@@ -0,0 +1,559 @@
+use std::collections::HashSet;
+
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+const ENGLISH_STOP_WORDS: &[&str] = &[
+    "i",
+    "me",
+    "my",
+    "myself",
+    "we",
+    "our",
+    "ours",
+    "ourselves",
+    "you",
+    "your",
+    "yours",
+    "yourself",
+    "yourselves",
+    "he",
+    "him",
+    "his",
+    "himself",
+    "she",
+    "her",
+    "hers",
+    "herself",
+    "it",
+    "its",
+    "itself",
+    "they",
+    "them",
+    "their",
+    "theirs",
+    "themselves",
+    "what",
+    "which",
+    "who",
+    "whom",
+    "this",
+    "that",
+    "these",
+    "those",
+    "am",
+    "is",
+    "are",
+    "was",
+    "were",
+    "be",
+    "been",
+    "being",
+    "have",
+    "has",
+    "had",
+    "having",
+    "do",
+    "does",
+    "did",
+    "doing",
+    "a",
+    "an",
+    "the",
+    "and",
+    "but",
+    "if",
+    "or",
+    "because",
+    "as",
+    "until",
+    "while",
+    "of",
+    "at",
+    "by",
+    "for",
+    "with",
+    "about",
+    "against",
+    "between",
+    "into",
+    "through",
+    "during",
+    "before",
+    "after",
+    "above",
+    "below",
+    "to",
+    "from",
+    "up",
+    "down",
+    "in",
+    "out",
+    "on",
+    "off",
+    "over",
+    "under",
+    "again",
+    "further",
+    "then",
+    "once",
+    "here",
+    "there",
+    "when",
+    "where",
+    "why",
+    "how",
+    "all",
+    "any",
+    "both",
+    "each",
+    "few",
+    "more",
+    "most",
+    "other",
+    "some",
+    "such",
+    "no",
+    "nor",
+    "not",
+    "only",
+    "own",
+    "same",
+    "so",
+    "than",
+    "too",
+    "very",
+    "s",
+    "t",
+    "can",
+    "will",
+    "just",
+    "don",
+    "should",
+    "now",
+];
+
+const ITALIAN_STOP_WORDS: &[&str] = &[
+    "a", "ad", "agli", "ai", "al", "alla", "alle", "allo", "anche", "che", "chi", "ci", "come",
+    "con", "contro", "da", "dal", "dalla", "dei", "del", "della", "delle", "dello", "di", "e",
+    "ed", "gli", "ha", "hanno", "ho", "i", "il", "in", "io", "la", "le", "lei", "lo", "loro",
+    "lui", "ma", "mi", "ne", "nei", "nel", "nella", "noi", "non", "o", "per", "più", "quale",
+    "quando", "quello", "questo", "se", "si", "sono", "su", "sul", "sulla", "ti", "tra", "tu",
+    "un", "una", "uno", "voi",
+];
+
+const GERMAN_STOP_WORDS: &[&str] = &[
+    "aber", "als", "am", "an", "auch", "auf", "aus", "bei", "bin", "bis", "das", "dass", "dem",
+    "den", "der", "des", "die", "doch", "du", "ein", "eine", "einem", "einen", "einer", "eines",
+    "er", "es", "für", "hat", "ich", "im", "in", "ist", "ja", "kein", "mit", "nach", "nicht",
+    "noch", "nur", "oder", "sich", "sie", "sind", "so", "über", "um", "und", "uns", "von", "vor",
+    "war", "was", "wie", "wir", "zu", "zum", "zur",
+];
+
+const FRENCH_STOP_WORDS: &[&str] = &[
+    "au", "aux", "avec", "c", "ce", "ces", "d", "dans", "de", "des", "du", "elle", "en", "est",
+    "et", "eux", "il", "ils", "j", "je", "l", "la", "le", "les", "leur", "lui", "m", "ma", "mais",
+    "me", "même", "mes", "moi", "mon", "n", "ne", "nos", "notre", "nous", "on", "ou", "par", "pas",
+    "pour", "qu", "que", "qui", "s", "sa", "se", "ses", "son", "sont", "sur", "t", "ta", "te",
+    "tes", "toi", "ton", "tu", "un", "une", "vos", "votre", "vous", "y",
+];
+
+const SPANISH_STOP_WORDS: &[&str] = &[
+    "a", "al", "algo", "como", "con", "de", "del", "el", "ella", "ellos", "en", "entre", "era",
+    "es", "esta", "este", "esto", "fue", "ha", "la", "las", "le", "les", "lo", "los", "me", "mi",
+    "muy", "más", "no", "nos", "o", "para", "pero", "por", "que", "se", "si", "sin", "sobre", "su",
+    "sus", "también", "te", "tu", "un", "una", "uno", "y", "ya", "yo",
+];
+
+/// Tokenizers are shared by every search on an `Index`, so they must be
+/// `Send + Sync` for the index to be queried from several threads.
+pub trait Tokenizer: Send + Sync {
+    fn tokenize(&self, text: &str) -> Vec<String>;
+
+    fn settings(&self) -> Option<TokenizerSettings> {
+        None
+    }
+
+    fn case_sensitive(&self) -> bool {
+        false
+    }
+
+    /// Normalizes a word fragment, such as a search prefix, the way
+    /// `tokenize` normalizes words, but without stemming, so it can be
+    /// compared with the start of indexed tokens.
+    fn normalize_fragment(&self, fragment: &str) -> String {
+        if self.case_sensitive() {
+            fragment.to_string()
+        } else {
+            fragment.to_lowercase()
+        }
+    }
+}
+
+/// How words containing an apostrophe (`'` or `’`) are tokenized.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ApostrophePolicy {
+    /// Drop the apostrophe and join the pieces: "don't" is "dont".
+    #[default]
+    Join,
+    /// Tokenize each piece on its own: "don't" is "don" and "t", which the
+    /// English stop words both cover, and "rust's" is "rust" and "s".
+    Split,
+    /// Drop a trailing possessive `'s` and join the rest: "rust's" is "rust",
+    /// "it's" is "it" and "don't" is "dont".
+    StripPossessive,
+}
+
+impl ApostrophePolicy {
+    fn pieces(self, word: &str) -> Vec<&str> {
+        match self {
+            Self::Join => vec![word],
+            Self::Split => word.split(is_apostrophe).collect(),
+            Self::StripPossessive => match word.rfind(is_apostrophe) {
+                Some(i)
+                    if word[i..]
+                        .chars()
+                        .skip(1)
+                        .filter(|c| c.is_alphanumeric())
+                        .eq(['s']) =>
+                {
+                    vec![&word[..i]]
+                }
+                _ => vec![word],
+            },
+        }
+    }
+}
+
+const fn is_apostrophe(c: char) -> bool {
+    matches!(c, '\'' | '\u{2019}')
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenizerSettings {
+    pub language: Algorithm,
+    pub stemming: bool,
+    pub fold_diacritics: bool,
+    pub min_token_len: usize,
+    pub max_token_len: usize,
+    pub stop_words: Vec<String>,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    #[serde(default)]
+    pub apostrophes: ApostrophePolicy,
+}
+
+/// Maps a word to the form stored in the index, e.g. a lemmatizer lookup.
+pub type Normalizer = Box<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Splits text on whitespace and keeps the alphanumeric characters of each
+/// word.
+///
+/// A word containing a digit, such as "2021", "covid19" or "3.14", is
+/// indexed as-is without stemming; a `.` between two digits is kept, so
+/// "3.14" does not become "314", while other punctuation is dropped as usual.
+pub struct DefaultTokenizer {
+    language: Algorithm,
+    stemmer: Stemmer,
+    stemming: bool,
+    fold_diacritics: bool,
+    min_token_len: usize,
+    max_token_len: usize,
+    stop_words: HashSet<String>,
+    case_sensitive: bool,
+    apostrophes: ApostrophePolicy,
+    normalizer: Option<Normalizer>,
+}
+
+impl Default for DefaultTokenizer {
+    fn default() -> Self {
+        Self::new(Algorithm::English)
+    }
+}
+
+impl DefaultTokenizer {
+    #[must_use]
+    pub fn new(language: Algorithm) -> Self {
+        Self {
+            language,
+            stemmer: Stemmer::create(language),
+            stemming: true,
+            fold_diacritics: false,
+            min_token_len: 1,
+            max_token_len: usize::MAX,
+            stop_words: Self::default_stop_words(language)
+                .iter()
+                .map(|word| (*word).to_string())
+                .collect(),
+            case_sensitive: false,
+            apostrophes: ApostrophePolicy::default(),
+            normalizer: None,
+        }
+    }
+
+    #[must_use]
+    pub fn stop_words(mut self, stop_words: Vec<String>) -> Self {
+        self.stop_words = stop_words.into_iter().collect();
+        self
+    }
+
+    #[must_use]
+    pub const fn stemming(mut self, stemming: bool) -> Self {
+        self.stemming = stemming;
+        self
+    }
+
+    #[must_use]
+    pub const fn fold_diacritics(mut self, fold_diacritics: bool) -> Self {
+        self.fold_diacritics = fold_diacritics;
+        self
+    }
+
+    #[must_use]
+    pub const fn token_length(mut self, min_token_len: usize, max_token_len: usize) -> Self {
+        self.min_token_len = min_token_len;
+        self.max_token_len = max_token_len;
+        self
+    }
+
+    #[must_use]
+    pub const fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    #[must_use]
+    pub const fn apostrophes(mut self, apostrophes: ApostrophePolicy) -> Self {
+        self.apostrophes = apostrophes;
+        self
+    }
+
+    /// Normalizes each word with `normalizer` instead of the stemmer. The
+    /// function cannot be saved, so `settings` returns `None` once it is set.
+    #[must_use]
+    pub fn normalizer<F>(mut self, normalizer: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.normalizer = Some(Box::new(normalizer));
+        self
+    }
+
+    /// The stop words `new` uses for `language`: bundled lists for English,
+    /// Italian, German, French and Spanish, none for the other languages.
+    #[must_use]
+    pub const fn default_stop_words(language: Algorithm) -> &'static [&'static str] {
+        match language {
+            Algorithm::English => ENGLISH_STOP_WORDS,
+            Algorithm::Italian => ITALIAN_STOP_WORDS,
+            Algorithm::German => GERMAN_STOP_WORDS,
+            Algorithm::French => FRENCH_STOP_WORDS,
+            Algorithm::Spanish => SPANISH_STOP_WORDS,
+            _ => &[],
+        }
+    }
+
+    #[must_use]
+    pub const fn language(&self) -> Algorithm {
+        self.language
+    }
+}
+
+impl From<TokenizerSettings> for DefaultTokenizer {
+    fn from(settings: TokenizerSettings) -> Self {
+        Self::new(settings.language)
+            .stemming(settings.stemming)
+            .fold_diacritics(settings.fold_diacritics)
+            .token_length(settings.min_token_len, settings.max_token_len)
+            .stop_words(settings.stop_words)
+            .case_sensitive(settings.case_sensitive)
+            .apostrophes(settings.apostrophes)
+    }
+}
+
+impl Tokenizer for DefaultTokenizer {
+    fn settings(&self) -> Option<TokenizerSettings> {
+        if self.normalizer.is_some() {
+            return None;
+        }
+
+        let mut stop_words: Vec<String> = self.stop_words.iter().cloned().collect();
+        stop_words.sort_unstable();
+
+        Some(TokenizerSettings {
+            language: self.language,
+            stemming: self.stemming,
+            fold_diacritics: self.fold_diacritics,
+            min_token_len: self.min_token_len,
+            max_token_len: self.max_token_len,
+            stop_words,
+            case_sensitive: self.case_sensitive,
+            apostrophes: self.apostrophes,
+        })
+    }
+
+    fn case_sensitive(&self) -> bool {
+        self.case_sensitive
+    }
+
+    fn normalize_fragment(&self, fragment: &str) -> String {
+        let mut fragment: String = fragment.nfc().collect();
+        if !self.case_sensitive {
+            fragment = fragment.to_lowercase();
+        }
+        if self.fold_diacritics {
+            fragment = fold(&fragment);
+        }
+        fragment
+    }
+
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        let normalized: String = text.nfc().collect();
+
+        let normalized = if self.case_sensitive {
+            normalized
+        } else {
+            normalized.to_lowercase()
+        };
+
+        normalized
+            .split_whitespace()
+            .flat_map(|w| self.apostrophes.pieces(w))
+            .filter_map(|w| {
+                // Stop words are checked before folding, so accented ones
+                // still match, and again after it for lists given folded.
+                let is_stop_word = |word: &str| self.stop_words.contains(&word.to_lowercase());
+                let mut word = word_chars(w);
+                if is_stop_word(&word) {
+                    return None;
+                }
+                if self.fold_diacritics {
+                    word = fold(&word);
+                }
+
+                let length = word.chars().count();
+                let out_of_range = length < self.min_token_len || length > self.max_token_len;
+                if out_of_range || (self.fold_diacritics && is_stop_word(&word)) {
+                    None
+                } else if word.chars().any(char::is_numeric) {
+                    Some(word)
+                } else if let Some(normalizer) = &self.normalizer {
+                    Some(normalizer(&word))
+                } else if self.stemming {
+                    Some(self.stemmer.stem(&word).into_owned())
+                } else {
+                    Some(word)
+                }
+            })
+            .collect()
+    }
+}
+
+fn fold(word: &str) -> String {
+    word.nfd().filter(|c| !is_combining_mark(*c)).collect()
+}
+
+fn word_chars(word: &str) -> String {
+    let chars: Vec<char> = word.chars().collect();
+    chars
+        .iter()
+        .enumerate()
+        .filter(|&(i, c)| {
+            c.is_alphanumeric()
+                || (*c == '.'
+                    && i > 0
+                    && chars[i - 1].is_numeric()
+                    && chars.get(i + 1).is_some_and(|c| c.is_numeric()))
+        })
+        .map(|(_, c)| *c)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folding_keeps_accented_stop_words_out() {
+        let italian = DefaultTokenizer::new(Algorithm::Italian)
+            .stemming(false)
+            .fold_diacritics(true);
+        assert_eq!(italian.tokenize("più caffè"), vec!["caffe"]);
+
+        let german = DefaultTokenizer::new(Algorithm::German)
+            .stemming(false)
+            .fold_diacritics(true);
+        assert_eq!(german.tokenize("Für über Straße"), vec!["straße"]);
+    }
+
+    #[test]
+    fn folding_matches_folded_custom_stop_words() {
+        let tokenizer = DefaultTokenizer::new(Algorithm::English)
+            .stemming(false)
+            .fold_diacritics(true)
+            .stop_words(vec!["cafe".to_string()]);
+        assert_eq!(tokenizer.tokenize("Café crème"), vec!["creme"]);
+    }
+
+    #[test]
+    fn folding_handles_decomposed_input() {
+        let tokenizer = DefaultTokenizer::new(Algorithm::English)
+            .stemming(false)
+            .fold_diacritics(true);
+        assert_eq!(
+            tokenizer.tokenize("cafe\u{301} naïve"),
+            vec!["cafe", "naive"]
+        );
+    }
+
+    #[test]
+    fn apostrophe_policies() {
+        let tokenize = |policy| {
+            DefaultTokenizer::new(Algorithm::English)
+                .stemming(false)
+                .stop_words(Vec::new())
+                .apostrophes(policy)
+                .tokenize("don\u{2019}t rust's")
+        };
+        assert_eq!(tokenize(ApostrophePolicy::Join), vec!["dont", "rusts"]);
+        assert_eq!(
+            tokenize(ApostrophePolicy::Split),
+            vec!["don", "t", "rust", "s"]
+        );
+        assert_eq!(
+            tokenize(ApostrophePolicy::StripPossessive),
+            vec!["dont", "rust"]
+        );
+    }
+
+    #[test]
+    fn words_with_digits_are_kept_whole() {
+        let tokenizer = DefaultTokenizer::new(Algorithm::English);
+        assert_eq!(
+            tokenizer.tokenize("Running python3.12 at 3.5 GHz, version 2.0."),
+            vec!["run", "python3.12", "3.5", "ghz", "version", "2.0"]
+        );
+    }
+
+    #[test]
+    fn token_length_limits() {
+        let tokenizer = DefaultTokenizer::new(Algorithm::English)
+            .stemming(false)
+            .token_length(3, 5);
+        assert_eq!(
+            tokenizer.tokenize("go rust python zig"),
+            vec!["rust", "zig"]
+        );
+    }
+
+    #[test]
+    fn case_sensitive_tokens_keep_their_case() {
+        let tokenizer = DefaultTokenizer::new(Algorithm::English)
+            .stemming(false)
+            .case_sensitive(true);
+        assert_eq!(tokenizer.tokenize("Rust rust The"), vec!["Rust", "rust"]);
+        assert!(Tokenizer::case_sensitive(&tokenizer));
+    }
+}
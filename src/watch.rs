@@ -0,0 +1,139 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::{CercamiError, Index};
+
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// An index that is rebuilt in the background whenever its source file
+/// changes. Dropping the handle stops watching.
+pub struct WatchHandle {
+    shared: Arc<Shared>,
+    _watcher: RecommendedWatcher,
+}
+
+struct Shared {
+    index: RwLock<Arc<Index>>,
+    reloads: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+impl WatchHandle {
+    /// The current index. A reload swaps in a new one without affecting
+    /// searches that still hold the previous one.
+    #[must_use]
+    pub fn index(&self) -> Arc<Index> {
+        let index = self
+            .shared
+            .index
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(&index)
+    }
+
+    /// How many times the index has been rebuilt since watching started.
+    #[must_use]
+    pub fn reloads(&self) -> u64 {
+        self.shared.reloads.load(Ordering::Relaxed)
+    }
+
+    /// Why the latest rebuild failed, if it did. The previous index keeps
+    /// being served until a rebuild succeeds.
+    #[must_use]
+    pub fn last_error(&self) -> Option<String> {
+        self.shared
+            .last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl Shared {
+    fn reload(&self, result: Result<Index, CercamiError>) {
+        let mut last_error = self
+            .last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match result {
+            Ok(index) => {
+                *self.index.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(index);
+                self.reloads.fetch_add(1, Ordering::Relaxed);
+                *last_error = None;
+            }
+            Err(err) => *last_error = Some(err.to_string()),
+        }
+    }
+}
+
+impl Index {
+    /// Builds an index from `path` and rebuilds it with `Index::new` each
+    /// time the file changes, once writes have settled for half a second.
+    pub fn watch(path: &str) -> Result<WatchHandle, CercamiError> {
+        Self::watch_with(path, DEBOUNCE, Self::new)
+    }
+
+    /// Like `watch`, but rebuilds with `build` and waits `debounce` after the
+    /// last change before doing so.
+    pub fn watch_with<F>(
+        path: &str,
+        debounce: Duration,
+        build: F,
+    ) -> Result<WatchHandle, CercamiError>
+    where
+        F: Fn(&str) -> Result<Self, CercamiError> + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            index: RwLock::new(Arc::new(build(path)?)),
+            reloads: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+        });
+
+        // Watch the directory, since editors often replace the file rather
+        // than write to it.
+        let target = PathBuf::from(path);
+        let dir = match target.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+        let touches = move |event: &notify::Result<Event>| {
+            matches!(event, Ok(event) if !event.kind.is_access()
+                && event.paths.iter().any(|p| p.file_name() == target.file_name()))
+        };
+        let path = path.to_string();
+        let state = Arc::clone(&shared);
+        thread::spawn(move || {
+            while let Ok(event) = events.recv() {
+                if !touches(&event) {
+                    continue;
+                }
+                // Wait until the file has not changed for `debounce`.
+                let mut settled = Instant::now() + debounce;
+                loop {
+                    match events.recv_timeout(settled.saturating_duration_since(Instant::now())) {
+                        Ok(event) if touches(&event) => settled = Instant::now() + debounce,
+                        Ok(_) => {}
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                state.reload(build(&path));
+            }
+        });
+
+        Ok(WatchHandle {
+            shared,
+            _watcher: watcher,
+        })
+    }
+}